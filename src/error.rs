@@ -0,0 +1,231 @@
+use std::fmt::{Display, Formatter};
+
+use crate::{expr::Span, function::FunctionKey};
+
+/// 本库所有操作的错误类型，调用方可以按种类分别处理。
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcError {
+    /// 词法错误：无法识别的字符、非法的数字字面量
+    Lex { message: String, span: Span },
+    /// 语法错误
+    Parse { message: String, span: Span },
+    /// 调用了未定义的函数；在解析函数定义时发现的带有位置
+    UnknownFunction {
+        name: String,
+        arity: usize,
+        span: Option<Span>,
+    },
+    /// 实参个数与形参个数不一致
+    ArityMismatch { expected: usize, found: usize },
+    /// 表达式中出现了不是参数的变量
+    UnknownVariable(String),
+    /// 函数签名不是 `name(x, y, ...)` 的形式
+    IllegalFunction,
+    /// 求值结果超出定义域，例如被积函数在某点不是有限值
+    Domain(String),
+    /// 无法对该表达式求导
+    UnsupportedDerivative(String),
+    /// 无法对该表达式求值，例如等式
+    UnsupportedExpression(String),
+    /// 找不到初等原函数
+    NoClosedForm(String),
+    /// 积分结果求导后与被积函数不一致
+    IntegrationCheckFailed(String),
+    /// 已存在同名同参但表达式不同的函数
+    Redefinition { name: String, arity: usize },
+    /// 定义会造成循环调用
+    RecursiveDefinition {
+        name: String,
+        arity: usize,
+        callee: FunctionKey,
+    },
+    /// 函数仍被其他函数依赖，不能删除
+    InUse {
+        name: String,
+        arity: usize,
+        dependents: Vec<FunctionKey>,
+    },
+    /// 数值积分在求值次数上限内没有达到要求的精度
+    NotConverged {
+        value: f64,
+        error: f64,
+        evaluations: usize,
+    },
+    /// 参数不合法，例如积分限为 NaN、各列长度不同
+    InvalidArgument(String),
+    /// 读写文件失败
+    Io(String),
+    /// 工作区文件第 `line` 行（从 1 开始）有错误
+    Workspace { line: usize, error: Box<CalcError> },
+    /// 检查模式下某个子表达式的结果不是有限值
+    NonFinite {
+        /// 子表达式所在的函数
+        function: FunctionKey,
+        /// 从函数体根节点到该子表达式依次经过的子节点下标
+        path: Vec<usize>,
+        expr: String,
+        /// 该子表达式各操作数的值
+        operands: Vec<f64>,
+        value: f64,
+    },
+}
+
+impl CalcError {
+    /// 错误在输入中的位置，只有词法、语法错误和定义中的未知函数带有位置。
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CalcError::Lex { span, .. } | CalcError::Parse { span, .. } => Some(*span),
+            CalcError::UnknownFunction { span, .. } => *span,
+            _ => None,
+        }
+    }
+
+    /// 把位置整体后移 `offset` 个字符，用于被解析的文本只是一行输入的一部分时。
+    pub fn shift(self, offset: usize) -> CalcError {
+        let shift = |span: Span| Span::new(span.start + offset, span.end + offset);
+        match self {
+            CalcError::Lex { message, span } => CalcError::Lex {
+                message,
+                span: shift(span),
+            },
+            CalcError::Parse { message, span } => CalcError::Parse {
+                message,
+                span: shift(span),
+            },
+            CalcError::UnknownFunction { name, arity, span } => CalcError::UnknownFunction {
+                name,
+                arity,
+                span: span.map(shift),
+            },
+            other => other,
+        }
+    }
+
+    /// 不含位置的错误描述。
+    pub fn message(&self) -> String {
+        match self {
+            CalcError::Lex { message, .. } | CalcError::Parse { message, .. } => message.clone(),
+            CalcError::UnknownFunction { name, arity, .. } => {
+                format!("unknown function: {}/{}", name, arity)
+            }
+            CalcError::ArityMismatch { expected, found } => {
+                format!(
+                    "argument length mismatch, expect {} found {}",
+                    expected, found
+                )
+            }
+            CalcError::UnknownVariable(name) => format!("unknown variable: {}", name),
+            CalcError::IllegalFunction => "illegal function".to_string(),
+            CalcError::Domain(message) => format!("domain error: {}", message),
+            CalcError::UnsupportedDerivative(expr) => {
+                format!("derivative for {} is not implemented", expr)
+            }
+            CalcError::UnsupportedExpression(expr) => format!("cannot evaluate {}", expr),
+            CalcError::NoClosedForm(expr) => format!("no closed form found for {}", expr),
+            CalcError::IntegrationCheckFailed(expr) => {
+                format!("integration check failed for {}", expr)
+            }
+            CalcError::Redefinition { name, arity } => format!(
+                "{}/{} is already defined with a different expression",
+                name, arity
+            ),
+            CalcError::RecursiveDefinition {
+                name,
+                arity,
+                callee,
+            } => format!(
+                "recursive definition: {}/{} calls {}/{} which depends on it",
+                name, arity, callee.0, callee.1
+            ),
+            CalcError::InUse {
+                name,
+                arity,
+                dependents,
+            } => {
+                let list: Vec<String> = dependents
+                    .iter()
+                    .map(|(n, c)| format!("{}/{}", n, c))
+                    .collect();
+                format!(
+                    "cannot delete {}/{}: used by {}",
+                    name,
+                    arity,
+                    list.join(", ")
+                )
+            }
+            CalcError::NotConverged {
+                value,
+                error,
+                evaluations,
+            } => format!(
+                "quadrature did not converge after {} evaluations: value ≈ {}, error ≈ {:e}",
+                evaluations, value, error
+            ),
+            CalcError::InvalidArgument(message) => message.clone(),
+            CalcError::Io(message) => format!("io error: {}", message),
+            CalcError::Workspace { line, error } => format!("line {}: {}", line, error),
+            CalcError::NonFinite {
+                function,
+                path,
+                expr,
+                operands,
+                value,
+            } => {
+                let operands: Vec<String> = operands.iter().map(|v| v.to_string()).collect();
+                let path: Vec<String> = path.iter().map(|i| i.to_string()).collect();
+                // 直接求值的表达式没有函数名
+                let location = match function.0.as_str() {
+                    "" => String::new(),
+                    name => format!(" in {}/{}", name, function.1),
+                };
+                format!(
+                    "{} evaluates to {} with operands ({}){} at path [{}]",
+                    expr,
+                    value,
+                    operands.join(", "),
+                    location,
+                    path.join(", ")
+                )
+            }
+        }
+    }
+
+    /// 带位置的错误生成形如下面的图示，其余错误只输出描述：
+    ///
+    /// ```text
+    /// error: expect Operator: ) but find end of input
+    ///   | f(x)=(x+1
+    ///   |          ^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        match self.span() {
+            Some(span) => {
+                let width = (span.end.max(span.start + 1)) - span.start;
+                format!(
+                    "error: {}\n  | {}\n  | {}{}",
+                    self.message(),
+                    source,
+                    " ".repeat(span.start),
+                    "^".repeat(width)
+                )
+            }
+            None => format!("error: {}", self.message()),
+        }
+    }
+}
+
+impl Display for CalcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.span() {
+            Some(span) => write!(f, "{} at column {}", self.message(), span.start + 1),
+            None => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl std::error::Error for CalcError {}
+
+/// 输出错误信息，带位置的错误会画出指向 `source` 中出错位置的 `^`。
+pub fn render_error(err: &CalcError, source: &str) -> String {
+    err.render(source)
+}
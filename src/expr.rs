@@ -1,5 +1,7 @@
 use std::fmt::{Display, Formatter};
 
+use crate::error::CalcError;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Const(f64),
@@ -10,6 +12,7 @@ pub enum Expr {
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
     Power(Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
     Log(Box<Expr>, Box<Expr>),
     Trifuncs(String, Box<Expr>),
     Equal(Box<Expr>, Box<Expr>),
@@ -23,13 +26,17 @@ impl Display for Expr {
 
 impl Expr {
     /// 返回当前表达式的运算优先级（数字越大优先级越高）。
-    fn precedence(&self) -> u8 {
+    pub(crate) fn precedence(&self) -> u8 {
         match self {
             Expr::Add(_, _) | Expr::Sub(_, _) => 1,
             Expr::Mul(_, _) | Expr::Div(_, _) => 2,
-            Expr::Power(_, _) => 3,
+            // 负号比乘除结合得紧、比乘方结合得松：-x^2 即 -(x^2)，-x*y 即 (-x)*y
+            Expr::Neg(_) => 3,
+            // 负常数输出时带有前导 '-'，与一元负号同一优先级
+            Expr::Const(c) if c.is_sign_negative() => 3,
+            Expr::Power(_, _) => 4,
             Expr::Equal(_, _) => 0,
-            _ => 4,
+            _ => 5,
         }
     }
 
@@ -44,8 +51,8 @@ impl Expr {
 
         match self {
             Expr::Const(x) => {
-                // 将与自然常数 e 足够接近的常数以符号 e 显示
-                if (*x - std::f64::consts::E).abs() < 1e-12 {
+                // 自然常数显示为 e；e 可以是变量名，因此 `{:#}` 输出能精确读回的数字
+                if *x == std::f64::consts::E && !f.alternate() {
                     write!(f, "e")?
                 } else {
                     write!(f, "{}", x)?
@@ -76,10 +83,15 @@ impl Expr {
                 y.fmt_with_prec(f, my_prec + 1)?;
             }
             Expr::Power(x, y) => {
-                x.fmt_with_prec(f, my_prec)?;
+                // 右结合：底数在同一优先级上也要加括号，保证 (a^b)^c 不会输出成 a^b^c
+                x.fmt_with_prec(f, my_prec + 1)?;
                 write!(f, "^")?;
-                // 指数部分整体加括号更安全
-                y.fmt_with_prec(f, my_prec + 1)?;
+                // 指数允许一元负号和连续乘方，a^-b、a^b^c 无需括号
+                y.fmt_with_prec(f, my_prec - 1)?;
+            }
+            Expr::Neg(x) => {
+                write!(f, "-")?;
+                x.fmt_with_prec(f, my_prec)?;
             }
             Expr::Log(x, y) => {
                 write!(f, "log(")?;
@@ -117,6 +129,224 @@ impl Expr {
         Ok(())
     }
 
+    /// 输出 LaTeX 数学公式，例如 `\frac{1}{x^{2}}`。
+    pub fn to_latex(&self) -> String {
+        let mut out = String::new();
+        self.latex_with_prec(&mut out, 0);
+        out
+    }
+
+    /// 与 `fmt_with_prec` 相同的优先级规则，分式、根式和上标自带分组，因此无需括号。
+    fn latex_with_prec(&self, out: &mut String, parent_prec: u8) {
+        let my_prec = match self {
+            Expr::Div(_, _) => 5,
+            Expr::Power(_, y) if is_half(y) => 5,
+            _ => self.precedence(),
+        };
+        let need_paren = my_prec < parent_prec;
+
+        if need_paren {
+            out.push_str("\\left(");
+        }
+
+        match self {
+            Expr::Const(x) => out.push_str(&latex_const(*x)),
+            Expr::Var(x) => out.push_str(&latex_name(x)),
+            Expr::Add(x, y) => {
+                x.latex_with_prec(out, my_prec);
+                // x+-y 写成 x-y
+                match y.as_ref() {
+                    Expr::Neg(z) => {
+                        out.push_str(" - ");
+                        z.latex_with_prec(out, my_prec + 1);
+                    }
+                    Expr::Const(c) if c.is_sign_negative() => {
+                        out.push_str(" - ");
+                        out.push_str(&latex_const(-c));
+                    }
+                    _ => {
+                        out.push_str(" + ");
+                        y.latex_with_prec(out, my_prec + 1);
+                    }
+                }
+            }
+            Expr::Sub(x, y) => {
+                x.latex_with_prec(out, my_prec);
+                out.push_str(" - ");
+                y.latex_with_prec(out, my_prec + 1);
+            }
+            Expr::Mul(x, y) => {
+                x.latex_with_prec(out, my_prec);
+                // 系数与变量、函数之间省略乘号，如 2x、3\sin\left(x\right)
+                if !implicit_mul(x, y) {
+                    out.push_str(" \\cdot ");
+                } else if out.trim_end_matches(char::is_alphabetic).ends_with('\\') {
+                    // 控制词之后紧跟字母会连成另一个控制词，如 \pix，需要空格隔开
+                    out.push(' ');
+                }
+                y.latex_with_prec(out, my_prec + 1);
+            }
+            Expr::Div(x, y) => {
+                out.push_str("\\frac{");
+                x.latex_with_prec(out, 0);
+                out.push_str("}{");
+                y.latex_with_prec(out, 0);
+                out.push('}');
+            }
+            Expr::Power(x, y) if is_half(y) => {
+                out.push_str("\\sqrt{");
+                x.latex_with_prec(out, 0);
+                out.push('}');
+            }
+            Expr::Power(x, y) => {
+                if let Expr::Trifuncs(name, pvar) = x.as_ref()
+                    && matches!(y.as_ref(), Expr::Const(c) if *c > 0.0)
+                {
+                    // 三角函数的正数次幂写成 \sin^{2}\left(x\right)，负数次幂会与反函数混淆
+                    out.push_str(&format!("\\{}^{{", name));
+                    y.latex_with_prec(out, 0);
+                    out.push_str("}\\left(");
+                    pvar.latex_with_prec(out, 0);
+                    out.push_str("\\right)");
+                } else {
+                    // 分式作底数时同样需要括号
+                    if matches!(x.as_ref(), Expr::Div(_, _)) {
+                        out.push_str("\\left(");
+                        x.latex_with_prec(out, 0);
+                        out.push_str("\\right)");
+                    } else {
+                        x.latex_with_prec(out, my_prec + 1);
+                    }
+                    out.push_str("^{");
+                    y.latex_with_prec(out, 0);
+                    out.push('}');
+                }
+            }
+            Expr::Neg(x) => {
+                out.push('-');
+                x.latex_with_prec(out, my_prec);
+            }
+            Expr::Log(x, y) => {
+                // 以 e 为底的对数写成 \ln
+                if **x == Expr::Const(std::f64::consts::E) {
+                    out.push_str("\\ln");
+                } else {
+                    out.push_str("\\log_{");
+                    x.latex_with_prec(out, 0);
+                    out.push('}');
+                }
+                out.push_str("\\left(");
+                y.latex_with_prec(out, 0);
+                out.push_str("\\right)");
+            }
+            Expr::Trifuncs(name, pvar) => {
+                out.push_str(&format!("\\{}\\left(", name));
+                pvar.latex_with_prec(out, 0);
+                out.push_str("\\right)");
+            }
+            Expr::Func(name, args) => {
+                out.push_str(&latex_name(name));
+                out.push_str("\\left(");
+                for (i, arg) in args.iter().enumerate() {
+                    arg.latex_with_prec(out, 0);
+                    if i + 1 != args.len() {
+                        out.push_str(", ");
+                    }
+                }
+                out.push_str("\\right)");
+            }
+            Expr::Equal(x, y) => {
+                x.latex_with_prec(out, my_prec);
+                out.push_str(" = ");
+                y.latex_with_prec(out, my_prec);
+            }
+        }
+
+        if need_paren {
+            out.push_str("\\right)");
+        }
+    }
+
+    /// 把表达式中的变量 `params[i]` 同时替换为 `values[i]`。
+    pub fn substitute(&self, params: &[Expr], values: &[Expr]) -> Expr {
+        match self {
+            Expr::Var(_) => match params.iter().position(|p| p == self) {
+                Some(i) => values[i].clone(),
+                None => self.clone(),
+            },
+            Expr::Const(_) => self.clone(),
+            Expr::Func(name, args) => Expr::Func(
+                name.clone(),
+                args.iter().map(|a| a.substitute(params, values)).collect(),
+            ),
+            Expr::Add(x, y) => Expr::Add(
+                Box::new(x.substitute(params, values)),
+                Box::new(y.substitute(params, values)),
+            ),
+            Expr::Sub(x, y) => Expr::Sub(
+                Box::new(x.substitute(params, values)),
+                Box::new(y.substitute(params, values)),
+            ),
+            Expr::Mul(x, y) => Expr::Mul(
+                Box::new(x.substitute(params, values)),
+                Box::new(y.substitute(params, values)),
+            ),
+            Expr::Div(x, y) => Expr::Div(
+                Box::new(x.substitute(params, values)),
+                Box::new(y.substitute(params, values)),
+            ),
+            Expr::Power(x, y) => Expr::Power(
+                Box::new(x.substitute(params, values)),
+                Box::new(y.substitute(params, values)),
+            ),
+            Expr::Log(x, y) => Expr::Log(
+                Box::new(x.substitute(params, values)),
+                Box::new(y.substitute(params, values)),
+            ),
+            Expr::Equal(x, y) => Expr::Equal(
+                Box::new(x.substitute(params, values)),
+                Box::new(y.substitute(params, values)),
+            ),
+            Expr::Neg(x) => Expr::Neg(Box::new(x.substitute(params, values))),
+            Expr::Trifuncs(name, x) => {
+                Expr::Trifuncs(name.clone(), Box::new(x.substitute(params, values)))
+            }
+        }
+    }
+
+    /// 收集表达式中调用到的函数 (函数名, 参数个数)，按首次出现的顺序、不重复。
+    pub fn called_functions(&self) -> Vec<(String, usize)> {
+        let mut res = Vec::new();
+        self.collect_calls(&mut res);
+        res
+    }
+
+    fn collect_calls(&self, res: &mut Vec<(String, usize)>) {
+        match self {
+            Expr::Const(_) | Expr::Var(_) => {}
+            Expr::Func(name, args) => {
+                let key = (name.clone(), args.len());
+                if !res.contains(&key) {
+                    res.push(key);
+                }
+                for arg in args {
+                    arg.collect_calls(res);
+                }
+            }
+            Expr::Add(x, y)
+            | Expr::Sub(x, y)
+            | Expr::Mul(x, y)
+            | Expr::Div(x, y)
+            | Expr::Power(x, y)
+            | Expr::Log(x, y)
+            | Expr::Equal(x, y) => {
+                x.collect_calls(res);
+                y.collect_calls(res);
+            }
+            Expr::Neg(x) | Expr::Trifuncs(_, x) => x.collect_calls(res),
+        }
+    }
+
     /// 递归地简化表达式树，去掉诸如 +0、*1、*0 等冗余项。
     pub fn simplify(&self) -> Expr {
         match self {
@@ -179,6 +409,11 @@ impl Expr {
                     _ => Expr::Power(Box::new(l), Box::new(r)),
                 }
             }
+            Expr::Neg(x) => match x.simplify() {
+                Expr::Const(c) => Expr::Const(-c),
+                Expr::Neg(inner) => *inner,
+                inner => Expr::Neg(Box::new(inner)),
+            },
             Expr::Log(x, y) => {
                 let l = x.simplify();
                 let r = y.simplify();
@@ -206,38 +441,98 @@ pub enum Token {
     Trifuncs(String),
 }
 
+/// 乘积 `x*y` 是否可以省略乘号，例如系数与变量、函数相乘。
+pub(crate) fn implicit_mul(x: &Expr, y: &Expr) -> bool {
+    matches!(x, Expr::Const(c) if !c.is_sign_negative())
+        && match y {
+            Expr::Var(_) | Expr::Func(_, _) | Expr::Trifuncs(_, _) | Expr::Log(_, _) => true,
+            Expr::Power(b, _) => matches!(b.as_ref(), Expr::Var(_) | Expr::Func(_, _)),
+            _ => false,
+        }
+}
+
+/// 指数是否为 1/2，此时乘方写成根号。
+pub(crate) fn is_half(expr: &Expr) -> bool {
+    match expr {
+        Expr::Const(c) => *c == 0.5,
+        Expr::Div(x, y) => **x == Expr::Const(1.0) && **y == Expr::Const(2.0),
+        _ => false,
+    }
+}
+
+/// 常数的 LaTeX 形式，π 和 e 写成符号。
+fn latex_const(x: f64) -> String {
+    if x == std::f64::consts::PI {
+        "\\pi".to_string()
+    } else if x == std::f64::consts::E {
+        "e".to_string()
+    } else if x.is_nan() {
+        "\\mathrm{NaN}".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "\\infty" } else { "-\\infty" }.to_string()
+    } else {
+        x.to_string()
+    }
+}
+
+/// 变量名和函数名的 LaTeX 形式：`_` 之后和末尾的数字作为下标，多个字母的名字用正体，
+/// 例如 `f_xy` 得到 `f_{xy}`，`x1` 得到 `x_{1}`，`f'` 保持不变。
+pub(crate) fn latex_name(name: &str) -> String {
+    let primes = name.len() - name.trim_end_matches('\'').len();
+    let (base, primes) = name.split_at(name.len() - primes);
+
+    let (head, sub) = match base.split_once('_') {
+        Some((head, sub)) => (head, sub),
+        None => {
+            let digits = base.len() - base.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+            base.split_at(base.len() - digits)
+        }
+    };
+
+    let mut res = if head.chars().count() > 1 {
+        format!("\\operatorname{{{}}}", head)
+    } else {
+        head.to_string()
+    };
+    if !sub.is_empty() {
+        res.push_str(&format!("_{{{}}}", sub));
+    }
+    res.push_str(primes);
+    res
+}
+
 impl Token {
-    pub fn as_identifier(&self) -> Result<String, anyhow::Error> {
+    pub fn as_identifier(&self) -> Result<String, CalcError> {
         if let Token::Identifier(s) = self {
             Ok(s.to_string())
         } else {
-            Err(anyhow::Error::msg(
-                "please use Token.Identifier to call as_identifier",
+            Err(CalcError::InvalidArgument(
+                "please use Token.Identifier to call as_identifier".to_string(),
             ))
         }
     }
 
-    pub fn as_const(&self) -> Result<f64, anyhow::Error> {
+    pub fn as_const(&self) -> Result<f64, CalcError> {
         if let Token::Const(s) = self {
             Ok(*s)
         } else {
-            Err(anyhow::Error::msg(
-                "please use Token.Const to call as_const",
+            Err(CalcError::InvalidArgument(
+                "please use Token.Const to call as_const".to_string(),
             ))
         }
     }
 
-    pub fn as_operator(&self) -> Result<char, anyhow::Error> {
+    pub fn as_operator(&self) -> Result<char, CalcError> {
         if let Token::Operator(s) = self {
             Ok(*s)
         } else {
-            Err(anyhow::Error::msg(
-                "please use Token.Operator to call as_operator",
+            Err(CalcError::InvalidArgument(
+                "please use Token.Operator to call as_operator".to_string(),
             ))
         }
     }
 
-    pub fn get_type(&self) -> Result<String, anyhow::Error> {
+    pub fn get_type(&self) -> Result<String, CalcError> {
         if let Token::Identifier(_) = self {
             Ok("identifier".to_string())
         } else if let Token::Const(_) = self {
@@ -247,11 +542,37 @@ impl Token {
         } else if let Token::Log() = self {
             Ok("log".to_string())
         } else {
-            Err(anyhow::Error::msg("unkonwn type"))
+            Err(CalcError::InvalidArgument("unkonwn type".to_string()))
         }
     }
 }
 
+/// token 在输入中的位置，按字符计的左闭右开区间 `[start, end)`。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+/// 带有位置信息的 token，由 `Tokenlizer::tokenlize` 产生。
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+impl Display for SpannedToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} @{}..{}", self.token, self.span.start, self.span.end)
+    }
+}
+
 impl Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Token::Identifier(s) = self {
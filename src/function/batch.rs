@@ -0,0 +1,96 @@
+use std::{cell::RefCell, rc::Rc, thread};
+
+use crate::{
+    error::CalcError,
+    function::{Function, FunctionTable, compile::CompiledFunction},
+};
+
+/// 批量求值的执行方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchMode {
+    /// 在当前线程依次求值
+    Serial,
+    /// 把输入分块交给多个线程，`threads` 为 0 时使用可用的 CPU 数
+    Parallel { threads: usize },
+}
+
+impl CompiledFunction {
+    /// 按列批量求值：`columns[i]` 是第 i 个参数的全部取值，各列长度必须相同。
+    pub fn caculate_batch(
+        &self,
+        columns: &[&[f64]],
+        mode: BatchMode,
+    ) -> Result<Vec<f64>, CalcError> {
+        if columns.len() != self.arity() {
+            return Err(CalcError::ArityMismatch {
+                expected: self.arity(),
+                found: columns.len(),
+            });
+        }
+        let rows = columns.first().map_or(1, |c| c.len());
+        if columns.iter().any(|c| c.len() != rows) {
+            return Err(CalcError::InvalidArgument(
+                "all columns must have the same length".to_string(),
+            ));
+        }
+
+        let mut out = vec![0.0; rows];
+        let threads = match mode {
+            BatchMode::Serial => 1,
+            BatchMode::Parallel { threads: 0 } => {
+                thread::available_parallelism().map_or(1, |n| n.get())
+            }
+            BatchMode::Parallel { threads } => threads,
+        };
+
+        if threads <= 1 || rows < 2 * threads {
+            self.caculate_rows(columns, 0, &mut out)?;
+        } else {
+            let chunk = rows.div_ceil(threads);
+            thread::scope(|s| {
+                let handles: Vec<_> = out
+                    .chunks_mut(chunk)
+                    .enumerate()
+                    .map(|(i, part)| s.spawn(move || self.caculate_rows(columns, i * chunk, part)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .try_for_each(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            })?;
+        }
+
+        Ok(out)
+    }
+
+    /// 计算从第 `start` 行开始的 `out.len()` 行，整个过程只复用一个求值栈和参数缓冲区。
+    fn caculate_rows(
+        &self,
+        columns: &[&[f64]],
+        start: usize,
+        out: &mut [f64],
+    ) -> Result<(), CalcError> {
+        let mut stack = Vec::new();
+        let mut point = vec![0.0; columns.len()];
+
+        for (i, y) in out.iter_mut().enumerate() {
+            for (p, column) in point.iter_mut().zip(columns) {
+                *p = column[start + i];
+            }
+            *y = self.caculate_with(&point, &mut stack)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Function {
+    /// 编译后按列批量求值，见 [`CompiledFunction::caculate_batch`]。
+    pub fn caculate_batch(
+        &self,
+        columns: &[&[f64]],
+        mode: BatchMode,
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<Vec<f64>, CalcError> {
+        self.compile(function_table)?.caculate_batch(columns, mode)
+    }
+}
@@ -1,19 +1,54 @@
 use std::{cell::RefCell, rc::Rc};
 
 use crate::{
+    error::CalcError,
     expr::Expr,
-    function::{Function, FunctionTable},
+    function::{EvalMode, Function, FunctionTable},
 };
 
+/// 求值时按名字查找被调函数的方式。
+///
+/// `Rc<RefCell<FunctionTable>>` 只在遇到函数调用时才借用，
+/// 调用方持有可变借用时仍可以对不含调用的函数求值。
+trait FunctionLookup {
+    fn with_function<R>(
+        &self,
+        name: &str,
+        argc: usize,
+        f: impl FnOnce(Option<&Function>) -> R,
+    ) -> R;
+}
+
+impl FunctionLookup for FunctionTable {
+    fn with_function<R>(
+        &self,
+        name: &str,
+        argc: usize,
+        f: impl FnOnce(Option<&Function>) -> R,
+    ) -> R {
+        f(self.find(name, argc))
+    }
+}
+
+impl FunctionLookup for Rc<RefCell<FunctionTable>> {
+    fn with_function<R>(
+        &self,
+        name: &str,
+        argc: usize,
+        f: impl FnOnce(Option<&Function>) -> R,
+    ) -> R {
+        f(self.borrow().find(name, argc))
+    }
+}
+
 impl Function {
-    fn find_var(&self, var: String, args: &Vec<f64>) -> Result<f64, anyhow::Error> {
+    fn find_var(&self, var: String, args: &[f64]) -> Result<f64, CalcError> {
         if let Expr::Func(_, ref orig_args) = self.symble {
             if args.len() != orig_args.len() {
-                return Err(anyhow::Error::msg(format!(
-                    "argument length mismatch in find_var, args.len()={} orig_args.len()={}",
-                    args.len(),
-                    orig_args.len()
-                )));
+                return Err(CalcError::ArityMismatch {
+                    expected: orig_args.len(),
+                    found: args.len(),
+                });
             }
             for i in 0..orig_args.len() {
                 if let Expr::Var(name) = orig_args[i].clone()
@@ -24,10 +59,10 @@ impl Function {
             }
         }
 
-        Err(anyhow::Error::msg(format!("unknown variable: {}", var)))
+        Err(CalcError::UnknownVariable(var))
     }
 
-    fn caculate_trifuncs(&self, name: &String, var: f64) -> Result<f64, anyhow::Error> {
+    fn caculate_trifuncs(&self, name: &String, var: f64) -> Result<f64, CalcError> {
         if name == "sin" {
             return Ok(var.sin());
         } else if name == "cos" {
@@ -44,133 +79,177 @@ impl Function {
             return Ok(1.0/var.sin());
         } else if name == "sec" {
             return Ok(1.0/var.cos());
-        } else if name == "sot" {
+        } else if name == "cot" {
             return Ok(1.0/var.tan());
         }
 
-        Err(anyhow::Error::msg(format!(
-            "unknown trigonometric function: {}",
-            name
-        )))
+        Err(CalcError::UnsupportedExpression(name.to_string()))
     }
 
     pub fn caculate(
         &self,
-        arg: &Vec<f64>,
+        arg: &[f64],
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<f64, CalcError> {
+        self.caculate_with_mode(arg, function_table, EvalMode::Lenient)
+    }
+
+    /// 按 `mode` 在点 `arg` 处求值。
+    ///
+    /// `EvalMode::Checked` 下返回的 `CalcError::NonFinite` 指向最先得到 NaN 或 ±∞ 的子表达式，
+    /// 路径是该子表达式在所在函数体中的位置：二元运算左 0 右 1，`log` 底数 0 真数 1，
+    /// 一元运算和三角函数的参数为 0，函数调用的第 i 个实参为 i。
+    pub fn caculate_with_mode(
+        &self,
+        arg: &[f64],
         function_table: Rc<RefCell<FunctionTable>>,
-    ) -> Result<f64, anyhow::Error> {
+        mode: EvalMode,
+    ) -> Result<f64, CalcError> {
+        self.caculate_by(arg, &function_table, mode, &mut Vec::new())
+    }
+
+    /// 在点 `arg` 处求值，只读取 `function_table`，可在多个线程中共享同一张表。
+    pub fn caculate_in(
+        &self,
+        arg: &[f64],
+        function_table: &FunctionTable,
+    ) -> Result<f64, CalcError> {
+        self.caculate_by(arg, function_table, EvalMode::Lenient, &mut Vec::new())
+    }
+
+    /// 对函数体的第 `index` 个子表达式求值。
+    fn caculate_child(
+        &self,
+        index: usize,
+        expr: Expr,
+        arg: &[f64],
+        function_table: &impl FunctionLookup,
+        mode: EvalMode,
+        path: &mut Vec<usize>,
+    ) -> Result<f64, CalcError> {
+        let mut child = Function::new_with_expr(expr);
+        child.symble = self.symble.clone();
+
+        path.push(index);
+        let res = child.caculate_by(arg, function_table, mode, path)?;
+        path.pop();
+        Ok(res)
+    }
+
+    /// 检查模式下结果不是有限值时报错，`operands` 为该子表达式各操作数的值。
+    fn check(
+        &self,
+        value: f64,
+        operands: &[f64],
+        mode: EvalMode,
+        path: &[usize],
+    ) -> Result<f64, CalcError> {
+        if mode == EvalMode::Checked && !value.is_finite() {
+            return Err(CalcError::NonFinite {
+                function: self.key(),
+                path: path.to_vec(),
+                expr: self.body.to_string(),
+                operands: operands.to_vec(),
+                value,
+            });
+        }
+        Ok(value)
+    }
+
+    fn caculate_by(
+        &self,
+        arg: &[f64],
+        function_table: &impl FunctionLookup,
+        mode: EvalMode,
+        path: &mut Vec<usize>,
+    ) -> Result<f64, CalcError> {
         let pos = self.body.clone();
-        let orig_args;
-        if let Expr::Func(_, ref _orig_args) = self.symble {
-            orig_args = _orig_args.clone();
-        } else {
-            return Err(anyhow::Error::msg("unknown function"));
+        if !matches!(self.symble, Expr::Func(..)) {
+            return Err(CalcError::IllegalFunction);
         }
 
         if let Expr::Const(x) = pos {
-            #[cfg(debug_assertions)]
+            #[cfg(feature = "trace")]
             {
-                println!("Expr::Const({})", x);
+                eprintln!("Expr::Const({})", x);
             }
             Ok(x)
         } else if let Expr::Var(x) = pos {
-            #[cfg(debug_assertions)]
+            #[cfg(feature = "trace")]
             {
-                println!("Expr::Var({})", x);
-            }
-            match self.find_var(x, arg) {
-                Ok(v) => Ok(v),
-                Err(e) => Err(anyhow::Error::msg(format!("unknown var. {}", e))),
+                eprintln!("Expr::Var({})", x);
             }
+            let v = self.find_var(x, arg)?;
+            self.check(v, &[], mode, path)
         } else if let Expr::Add(x, y) = pos {
-            let mut l = Function::new_with_expr(*x);
-            let mut r = Function::new_with_expr(*y);
+            let l = self.caculate_child(0, *x, arg, function_table, mode, path)?;
+            let r = self.caculate_child(1, *y, arg, function_table, mode, path)?;
 
-            l.symble = Expr::Func("".to_string(), orig_args.clone());
-            r.symble = Expr::Func("".to_string(), orig_args.clone());
-
-            Ok(l.caculate(arg, function_table.clone())?
-                + r.caculate(arg, function_table.clone())?)
+            self.check(l + r, &[l, r], mode, path)
         } else if let Expr::Sub(x, y) = pos {
-            let mut l = Function::new_with_expr(*x);
-            let mut r = Function::new_with_expr(*y);
-
-            l.symble = Expr::Func("".to_string(), orig_args.clone());
-            r.symble = Expr::Func("".to_string(), orig_args.clone());
+            let l = self.caculate_child(0, *x, arg, function_table, mode, path)?;
+            let r = self.caculate_child(1, *y, arg, function_table, mode, path)?;
 
-            Ok(l.caculate(arg, function_table.clone())?
-                - r.caculate(arg, function_table.clone())?)
+            self.check(l - r, &[l, r], mode, path)
         } else if let Expr::Mul(x, y) = pos {
-            let mut l = Function::new_with_expr(*x);
-            let mut r = Function::new_with_expr(*y);
-
-            l.symble = Expr::Func("".to_string(), orig_args.clone());
-            r.symble = Expr::Func("".to_string(), orig_args.clone());
+            let l = self.caculate_child(0, *x, arg, function_table, mode, path)?;
+            let r = self.caculate_child(1, *y, arg, function_table, mode, path)?;
 
-            Ok(l.caculate(arg, function_table.clone())?
-                * r.caculate(arg, function_table.clone())?)
+            self.check(l * r, &[l, r], mode, path)
         } else if let Expr::Div(x, y) = pos {
-            let mut l = Function::new_with_expr(*x);
-            let mut r = Function::new_with_expr(*y);
+            let l = self.caculate_child(0, *x, arg, function_table, mode, path)?;
+            let r = self.caculate_child(1, *y, arg, function_table, mode, path)?;
 
-            l.symble = Expr::Func("".to_string(), orig_args.clone());
-            r.symble = Expr::Func("".to_string(), orig_args.clone());
-
-            Ok(l.caculate(arg, function_table.clone())?
-                / r.caculate(arg, function_table.clone())?)
+            self.check(l / r, &[l, r], mode, path)
         } else if let Expr::Power(x, y) = pos {
-            let mut l = Function::new_with_expr(*x);
-            let mut r = Function::new_with_expr(*y);
+            let l = self.caculate_child(0, *x, arg, function_table, mode, path)?;
+            let r = self.caculate_child(1, *y, arg, function_table, mode, path)?;
 
-            l.symble = Expr::Func("".to_string(), orig_args.clone());
-            r.symble = Expr::Func("".to_string(), orig_args.clone());
+            self.check(l.powf(r), &[l, r], mode, path)
+        } else if let Expr::Neg(x) = pos {
+            let v = self.caculate_child(0, *x, arg, function_table, mode, path)?;
 
-            Ok(l.caculate(arg, function_table.clone())?
-                .powf(r.caculate(arg, function_table.clone())?))
+            self.check(-v, &[v], mode, path)
         } else if let Expr::Log(x, y) = pos {
-            let mut l = Function::new_with_expr(*x);
-            let mut r = Function::new_with_expr(*y);
-
-            l.symble = Expr::Func("".to_string(), orig_args.clone());
-            r.symble = Expr::Func("".to_string(), orig_args.clone());
+            let l = self.caculate_child(0, *x, arg, function_table, mode, path)?;
+            let r = self.caculate_child(1, *y, arg, function_table, mode, path)?;
 
-            #[cfg(debug_assertions)]
+            #[cfg(feature = "trace")]
             {
-                println!(
-                    "log({},{})",
-                    r.caculate(arg, function_table.clone())?,
-                    l.caculate(arg, function_table.clone())?
-                );
+                eprintln!("log({},{})", r, l);
             }
 
-            Ok(r.caculate(arg, function_table.clone())?
-                .log(l.caculate(arg, function_table.clone())?))
+            self.check(r.log(l), &[l, r], mode, path)
         } else if let Expr::Trifuncs(ref name, pvar) = pos {
-            let mut var_func=Function::new_with_expr(*pvar);
-            var_func.symble = Expr::Func("".to_string(), orig_args.clone());
+            let v = self.caculate_child(0, *pvar, arg, function_table, mode, path)?;
 
-            Ok(self.caculate_trifuncs(name, var_func.caculate(arg, function_table)?)?)
+            self.check(self.caculate_trifuncs(name, v)?, &[v], mode, path)
         } else if let Expr::Func(name, args) = pos {
-            if let Some(func) = function_table.borrow().find(&name, args.len()) {
-                let mut argc = Vec::<f64>::new();
-                for i in args.clone() {
-                    let tfunc = Function::new_with_expr(i);
-                    argc.push(tfunc.caculate(arg, function_table.clone())?)
-                }
+            let mut argc = Vec::<f64>::new();
+            for (i, e) in args.iter().enumerate() {
+                argc.push(self.caculate_child(i, e.clone(), arg, function_table, mode, path)?);
+            }
+
+            function_table.with_function(&name, args.len(), |func| {
+                let Some(func) = func else {
+                    return Err(CalcError::UnknownFunction {
+                        name: name.clone(),
+                        arity: args.len(),
+                        span: None,
+                    });
+                };
 
-                #[cfg(debug_assertions)]
+                #[cfg(feature = "trace")]
                 {
-                    println!("Expr::Func({},{})", name, args.len());
+                    eprintln!("Expr::Func({},{})", name, args.len());
                 }
 
-                match func.caculate(&argc, function_table.clone()) {
-                    Ok(v) => return Ok(v),
-                    Err(e) => return Err(anyhow::Error::msg(format!("unknown function. {}", e))),
-                }
-            }
-            Err(anyhow::Error::msg("unknown caculation"))
+                // 被调函数内部的错误带有它自己的函数名和路径
+                let v = func.caculate_by(&argc, function_table, mode, &mut Vec::new())?;
+                self.check(v, &argc, mode, path)
+            })
         } else {
-            Err(anyhow::Error::msg("unknown caculation"))
+            Err(CalcError::UnsupportedExpression(pos.to_string()))
         }
     }
 }
@@ -0,0 +1,285 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    error::CalcError,
+    expr::Expr,
+    function::{Function, FunctionKey, FunctionTable},
+};
+
+/// 三角函数族，编译时由名字解析。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trig {
+    Sin,
+    Cos,
+    Tan,
+    Arcsin,
+    Arccos,
+    Arctan,
+    Csc,
+    Sec,
+    Cot,
+}
+
+/// 栈式虚拟机的指令。
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Const(f64),
+    /// 读取当前调用帧的第 i 个参数
+    Var(usize),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Neg,
+    /// 栈顶为真数，次栈顶为底数
+    Log,
+    Trig(Trig),
+    /// 调用第 chunk 个代码块，栈顶 argc 个值为实参
+    Call {
+        chunk: usize,
+        argc: usize,
+    },
+}
+
+/// 一个函数体编译得到的指令序列。
+#[derive(Debug, Clone, PartialEq)]
+struct Chunk {
+    arity: usize,
+    code: Vec<Op>,
+}
+
+/// 编译后的函数：变量已解析为参数下标，调用的函数已编译为独立的代码块。
+///
+/// 编译结果是定义时的快照，之后修改 `FunctionTable` 不会影响它。
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledFunction {
+    /// 第 0 个代码块是入口
+    chunks: Vec<Chunk>,
+}
+
+impl Trig {
+    fn from_name(name: &str) -> Result<Trig, CalcError> {
+        Ok(match name {
+            "sin" => Trig::Sin,
+            "cos" => Trig::Cos,
+            "tan" => Trig::Tan,
+            "arcsin" => Trig::Arcsin,
+            "arccos" => Trig::Arccos,
+            "arctan" => Trig::Arctan,
+            "csc" => Trig::Csc,
+            "sec" => Trig::Sec,
+            "cot" => Trig::Cot,
+            _ => {
+                return Err(CalcError::UnsupportedExpression(name.to_string()));
+            }
+        })
+    }
+
+    fn apply(self, v: f64) -> f64 {
+        match self {
+            Trig::Sin => v.sin(),
+            Trig::Cos => v.cos(),
+            Trig::Tan => v.tan(),
+            Trig::Arcsin => v.asin(),
+            Trig::Arccos => v.acos(),
+            Trig::Arctan => v.atan(),
+            Trig::Csc => 1.0 / v.sin(),
+            Trig::Sec => 1.0 / v.cos(),
+            Trig::Cot => 1.0 / v.tan(),
+        }
+    }
+}
+
+/// 编译过程中的状态：已编译的被调函数及其代码块下标。
+struct Compiler<'a> {
+    chunks: Vec<Chunk>,
+    resolved: HashMap<FunctionKey, usize>,
+    function_table: &'a FunctionTable,
+}
+
+impl Compiler<'_> {
+    /// 编译函数，返回其代码块下标；同一个被调函数只编译一次。
+    fn function(&mut self, func: &Function) -> Result<usize, CalcError> {
+        let Expr::Func(_, params) = &func.symble else {
+            return Err(CalcError::IllegalFunction);
+        };
+        let params: Vec<&str> = params
+            .iter()
+            .map(|p| match p {
+                Expr::Var(name) => Ok(name.as_str()),
+                _ => Err(CalcError::IllegalFunction),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut code = Vec::new();
+        self.expr(&func.body, &params, &mut code)?;
+
+        self.chunks.push(Chunk {
+            arity: params.len(),
+            code,
+        });
+        Ok(self.chunks.len() - 1)
+    }
+
+    fn expr(&mut self, expr: &Expr, params: &[&str], code: &mut Vec<Op>) -> Result<(), CalcError> {
+        let binary = |s: &mut Self, x: &Expr, y: &Expr, op: Op, code: &mut Vec<Op>| {
+            s.expr(x, params, code)?;
+            s.expr(y, params, code)?;
+            code.push(op);
+            Ok::<(), CalcError>(())
+        };
+
+        match expr {
+            Expr::Const(c) => code.push(Op::Const(*c)),
+            Expr::Var(name) => match params.iter().position(|p| p == name) {
+                Some(i) => code.push(Op::Var(i)),
+                None => return Err(CalcError::UnknownVariable(name.clone())),
+            },
+            Expr::Add(x, y) => binary(self, x, y, Op::Add, code)?,
+            Expr::Sub(x, y) => binary(self, x, y, Op::Sub, code)?,
+            Expr::Mul(x, y) => binary(self, x, y, Op::Mul, code)?,
+            Expr::Div(x, y) => binary(self, x, y, Op::Div, code)?,
+            Expr::Power(x, y) => binary(self, x, y, Op::Pow, code)?,
+            Expr::Log(x, y) => binary(self, x, y, Op::Log, code)?,
+            Expr::Neg(x) => {
+                self.expr(x, params, code)?;
+                code.push(Op::Neg);
+            }
+            Expr::Trifuncs(name, x) => {
+                let trig = Trig::from_name(name)?;
+                self.expr(x, params, code)?;
+                code.push(Op::Trig(trig));
+            }
+            Expr::Func(name, args) => {
+                let key = (name.clone(), args.len());
+                let chunk = match self.resolved.get(&key) {
+                    Some(chunk) => *chunk,
+                    None => {
+                        let callee = self
+                            .function_table
+                            .find(name, args.len())
+                            .cloned()
+                            .ok_or_else(|| CalcError::UnknownFunction {
+                                name: name.to_string(),
+                                arity: args.len(),
+                                span: None,
+                            })?;
+                        let chunk = self.function(&callee)?;
+                        self.resolved.insert(key, chunk);
+                        chunk
+                    }
+                };
+                for arg in args {
+                    self.expr(arg, params, code)?;
+                }
+                code.push(Op::Call {
+                    chunk,
+                    argc: args.len(),
+                });
+            }
+            Expr::Equal(_, _) => return Err(CalcError::UnsupportedExpression(expr.to_string())),
+        }
+
+        Ok(())
+    }
+}
+
+impl CompiledFunction {
+    pub fn arity(&self) -> usize {
+        self.chunks[0].arity
+    }
+
+    /// 在点 `args` 处求值，每次调用分配一个求值栈。
+    pub fn caculate(&self, args: &[f64]) -> Result<f64, CalcError> {
+        let mut stack = Vec::new();
+        self.caculate_with(args, &mut stack)
+    }
+
+    /// 使用调用方提供的求值栈在点 `args` 处求值；栈在多次调用间复用时不再分配内存。
+    pub fn caculate_with(&self, args: &[f64], stack: &mut Vec<f64>) -> Result<f64, CalcError> {
+        if args.len() != self.arity() {
+            return Err(CalcError::ArityMismatch {
+                expected: self.arity(),
+                found: args.len(),
+            });
+        }
+
+        stack.clear();
+        stack.extend_from_slice(args);
+        self.run(0, 0, stack);
+        Ok(stack[stack.len() - 1])
+    }
+
+    /// 执行第 `chunk` 个代码块，参数位于 stack[base..]，结果压在栈顶。
+    fn run(&self, chunk: usize, base: usize, stack: &mut Vec<f64>) {
+        for op in &self.chunks[chunk].code {
+            match *op {
+                Op::Const(c) => stack.push(c),
+                Op::Var(i) => stack.push(stack[base + i]),
+                Op::Neg => {
+                    let v = stack.pop().unwrap_or(f64::NAN);
+                    stack.push(-v);
+                }
+                Op::Trig(trig) => {
+                    let v = stack.pop().unwrap_or(f64::NAN);
+                    stack.push(trig.apply(v));
+                }
+                Op::Call { chunk, argc } => {
+                    let frame = stack.len() - argc;
+                    self.run(chunk, frame, stack);
+                    let v = stack.pop().unwrap_or(f64::NAN);
+                    stack.truncate(frame);
+                    stack.push(v);
+                }
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow | Op::Log => {
+                    let y = stack.pop().unwrap_or(f64::NAN);
+                    let x = stack.pop().unwrap_or(f64::NAN);
+                    stack.push(match op {
+                        Op::Add => x + y,
+                        Op::Sub => x - y,
+                        Op::Mul => x * y,
+                        Op::Div => x / y,
+                        Op::Pow => x.powf(y),
+                        _ => y.log(x),
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Function {
+    /// 把函数编译为栈式字节码，用于反复求值。
+    pub fn compile(
+        &self,
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<CompiledFunction, CalcError> {
+        self.compile_in(&function_table.borrow())
+    }
+
+    /// 与 [`Function::compile`] 相同，但只借用 `function_table`。
+    pub fn compile_in(
+        &self,
+        function_table: &FunctionTable,
+    ) -> Result<CompiledFunction, CalcError> {
+        let mut compiler = Compiler {
+            chunks: Vec::new(),
+            resolved: HashMap::new(),
+            function_table,
+        };
+
+        // 入口必须是第 0 个代码块，先占位再回填
+        compiler.chunks.push(Chunk {
+            arity: 0,
+            code: Vec::new(),
+        });
+        let entry = compiler.function(self)?;
+        compiler.chunks.swap(0, entry);
+        compiler.chunks.pop();
+
+        Ok(CompiledFunction {
+            chunks: compiler.chunks,
+        })
+    }
+}
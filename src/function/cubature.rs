@@ -0,0 +1,354 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    error::CalcError,
+    expr::Expr,
+    function::{
+        Function, FunctionTable,
+        compile::CompiledFunction,
+        quadrature::{self, QuadratureOptions, QuadratureResult},
+    },
+};
+
+/// 一个积分变量及其上下限；上下限可以是外层积分变量的表达式，例如 y 从 0 到 x。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    pub var: String,
+    pub lower: Expr,
+    pub upper: Expr,
+}
+
+/// 多重积分的计算方式。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CubatureMethod {
+    /// 逐层嵌套的自适应 G7K15
+    Adaptive,
+    /// 伪随机 Monte Carlo，相同的 `seed` 给出相同的结果
+    MonteCarlo { samples: usize, seed: u64 },
+    /// 基于 Sobol 序列的拟 Monte Carlo
+    QuasiMonteCarlo { samples: usize },
+}
+
+impl Limits {
+    pub fn new(var: &str, lower: Expr, upper: Expr) -> Limits {
+        Limits {
+            var: var.to_string(),
+            lower,
+            upper,
+        }
+    }
+}
+
+/// SplitMix64 伪随机数生成器。
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    fn new(seed: u64) -> SplitMix64 {
+        SplitMix64 { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// [0, 1) 上均匀分布的随机数。
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Joe–Kuo 方向数 (s, a, m_1..m_s)，对应第 2 到第 10 维；第 1 维为 van der Corput 序列。
+const SOBOL_PARAMS: [(u32, u32, &[u32]); 9] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+];
+
+const SOBOL_BITS: usize = 32;
+
+/// 用 Gray 码递推生成的 Sobol 序列，跳过原点。
+struct Sobol {
+    directions: Vec<[u32; SOBOL_BITS]>,
+    state: Vec<u32>,
+    index: u32,
+}
+
+impl Sobol {
+    fn new(dim: usize) -> Result<Sobol, CalcError> {
+        if dim > SOBOL_PARAMS.len() + 1 {
+            return Err(CalcError::InvalidArgument(format!(
+                "Sobol sequence supports at most {} dimensions",
+                SOBOL_PARAMS.len() + 1
+            )));
+        }
+
+        let mut directions = Vec::with_capacity(dim);
+        for d in 0..dim {
+            let mut v = [0u32; SOBOL_BITS];
+            if d == 0 {
+                for (k, vk) in v.iter_mut().enumerate() {
+                    *vk = 1 << (SOBOL_BITS - 1 - k);
+                }
+            } else {
+                let (s, a, m) = SOBOL_PARAMS[d - 1];
+                let s = s as usize;
+                for k in 0..SOBOL_BITS {
+                    v[k] = if k < s {
+                        m[k] << (SOBOL_BITS - 1 - k)
+                    } else {
+                        let mut x = v[k - s] ^ (v[k - s] >> s);
+                        for j in 1..s {
+                            if (a >> (s - 1 - j)) & 1 == 1 {
+                                x ^= v[k - j];
+                            }
+                        }
+                        x
+                    };
+                }
+            }
+            directions.push(v);
+        }
+
+        Ok(Sobol {
+            directions,
+            state: vec![0; dim],
+            index: 0,
+        })
+    }
+
+    fn next_point(&mut self, out: &mut [f64]) {
+        // 第 index 个点由上一个点异或 index 最低位 0 所在位置的方向数得到
+        let c = self.index.trailing_ones() as usize;
+        self.index += 1;
+        for ((x, v), o) in self.state.iter_mut().zip(&self.directions).zip(out) {
+            *x ^= v[c];
+            *o = *x as f64 / (1u64 << SOBOL_BITS) as f64;
+        }
+    }
+}
+
+impl Function {
+    /// 多重积分，`limits` 从最外层到最内层排列，其余参数按顺序取 `fixed` 中的值。
+    ///
+    /// 内层上下限可以引用外层积分变量和固定参数；Monte Carlo 类方法要求上下限有限。
+    pub fn multiple_integral(
+        &self,
+        limits: &[Limits],
+        fixed: &[f64],
+        method: CubatureMethod,
+        options: &QuadratureOptions,
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<QuadratureResult, CalcError> {
+        let params = self.params()?;
+
+        // 每个积分变量在参数表中的位置
+        let mut slots = Vec::with_capacity(limits.len());
+        for limit in limits {
+            let Some(index) = params
+                .iter()
+                .position(|p| *p == Expr::Var(limit.var.clone()))
+            else {
+                return Err(CalcError::UnknownVariable(limit.var.to_string()));
+            };
+            if slots.contains(&index) {
+                return Err(CalcError::InvalidArgument(format!(
+                    "variable {} is integrated twice",
+                    limit.var
+                )));
+            }
+            slots.push(index);
+        }
+
+        if fixed.len() + limits.len() != params.len() {
+            return Err(CalcError::ArityMismatch {
+                expected: params.len() - limits.len(),
+                found: fixed.len(),
+            });
+        }
+
+        // 上下限只能引用外层变量
+        for (i, limit) in limits.iter().enumerate() {
+            for inner in &limits[i..] {
+                if limit.lower.contains_var(&inner.var) || limit.upper.contains_var(&inner.var) {
+                    return Err(CalcError::InvalidArgument(format!(
+                        "limits of {} cannot depend on {}",
+                        limit.var, inner.var
+                    )));
+                }
+            }
+        }
+
+        // 被积函数和上下限都编译后再求值
+        let compile = |body: &Expr| {
+            Function {
+                symble: self.symble.clone(),
+                body: body.clone(),
+            }
+            .compile(function_table.clone())
+        };
+        let bounds = limits
+            .iter()
+            .map(|l| Ok((compile(&l.lower)?, compile(&l.upper)?)))
+            .collect::<Result<Vec<_>, CalcError>>()?;
+
+        // 非积分变量按顺序填入固定值
+        let mut point = vec![0.0; params.len()];
+        let mut rest = fixed.iter();
+        for (i, p) in point.iter_mut().enumerate() {
+            if !slots.contains(&i) {
+                *p = *rest.next().unwrap_or(&0.0);
+            }
+        }
+
+        let region = Region {
+            func: self.compile(function_table)?,
+            bounds,
+            slots: &slots,
+            stack: RefCell::new(Vec::new()),
+        };
+
+        match method {
+            CubatureMethod::Adaptive => {
+                let mut evaluations = 0;
+                let res = region.nested(0, &mut point, options, &mut evaluations)?;
+                Ok(QuadratureResult { evaluations, ..res })
+            }
+            CubatureMethod::MonteCarlo { samples, seed } => {
+                let mut rng = SplitMix64::new(seed);
+                region.monte_carlo(samples, &mut point, false, |u| {
+                    for x in u.iter_mut() {
+                        *x = rng.next_f64();
+                    }
+                })
+            }
+            CubatureMethod::QuasiMonteCarlo { samples } => {
+                let mut sobol = Sobol::new(limits.len())?;
+                region.monte_carlo(samples, &mut point, true, |u| sobol.next_point(u))
+            }
+        }
+    }
+}
+
+/// 积分区域：被积函数、每层上下限以及积分变量在参数表中的位置。
+struct Region<'a> {
+    func: CompiledFunction,
+    bounds: Vec<(CompiledFunction, CompiledFunction)>,
+    slots: &'a [usize],
+    /// 复用的求值栈
+    stack: RefCell<Vec<f64>>,
+}
+
+impl Region<'_> {
+    /// 在当前 `point`（外层变量已赋值）下计算第 `level` 层的上下限。
+    fn bounds_at(&self, level: usize, point: &[f64]) -> Result<(f64, f64), CalcError> {
+        let (lower, upper) = &self.bounds[level];
+        let mut stack = self.stack.borrow_mut();
+        Ok((
+            lower.caculate_with(point, &mut stack)?,
+            upper.caculate_with(point, &mut stack)?,
+        ))
+    }
+
+    fn value_at(&self, point: &[f64]) -> Result<f64, CalcError> {
+        self.func.caculate_with(point, &mut self.stack.borrow_mut())
+    }
+
+    /// 逐层嵌套的自适应积分，返回的误差是最外层的估计。
+    fn nested(
+        &self,
+        level: usize,
+        point: &mut Vec<f64>,
+        options: &QuadratureOptions,
+        evaluations: &mut usize,
+    ) -> Result<QuadratureResult, CalcError> {
+        let (a, b) = self.bounds_at(level, point)?;
+        let slot = self.slots[level];
+
+        let mut f = |x: f64| {
+            point[slot] = x;
+            if level + 1 == self.slots.len() {
+                *evaluations += 1;
+                self.value_at(point)
+            } else {
+                Ok(self.nested(level + 1, point, options, evaluations)?.value)
+            }
+        };
+        quadrature::integrate(&mut f, a, b, options)
+    }
+
+    /// 把 [0,1]^n 中的样本逐层映射到区域内求平均。
+    ///
+    /// 伪随机采样的误差为标准差 / √N；Sobol 序列没有方差估计，
+    /// 用前一半样本与全部样本结果之差作为误差估计。
+    fn monte_carlo(
+        &self,
+        samples: usize,
+        point: &mut Vec<f64>,
+        quasi: bool,
+        mut next: impl FnMut(&mut [f64]),
+    ) -> Result<QuadratureResult, CalcError> {
+        if samples < 2 {
+            return Err(CalcError::InvalidArgument(
+                "Monte Carlo needs at least 2 samples".to_string(),
+            ));
+        }
+
+        let mut u = vec![0.0; self.slots.len()];
+        let (mut sum, mut sum_sq, mut half_sum) = (0.0, 0.0, 0.0);
+
+        for n in 0..samples {
+            next(&mut u);
+            // 体积元为各层区间长度之积
+            let mut weight = 1.0;
+            for (level, t) in u.iter().enumerate() {
+                let (a, b) = self.bounds_at(level, point)?;
+                if !a.is_finite() || !b.is_finite() {
+                    return Err(CalcError::InvalidArgument(
+                        "Monte Carlo integration needs finite limits".to_string(),
+                    ));
+                }
+                point[self.slots[level]] = a + (b - a) * t;
+                weight *= b - a;
+            }
+
+            let y = self.value_at(point)? * weight;
+            if !y.is_finite() {
+                return Err(CalcError::Domain(format!(
+                    "integrand is not finite at {:?}: {}",
+                    point, y
+                )));
+            }
+            sum += y;
+            sum_sq += y * y;
+            if n < samples / 2 {
+                half_sum += y;
+            }
+        }
+
+        let n = samples as f64;
+        let value = sum / n;
+        let error = if quasi {
+            (value - half_sum / (samples / 2) as f64).abs()
+        } else {
+            ((sum_sq / n - value * value).max(0.0) / n).sqrt()
+        };
+
+        Ok(QuadratureResult {
+            value,
+            error,
+            evaluations: samples,
+        })
+    }
+}
@@ -1,15 +1,16 @@
 use std::{cell::RefCell, rc::Rc};
 
 use crate::{
+    error::CalcError,
     expr::Expr,
-    function::{Function, FunctionTable},
+    function::{DerivativeMode, Function, FunctionTable},
 };
 
 impl Expr {
-    fn find_var(&self, var: String, args: &Vec<Expr>) -> bool {
-        for i in 0..args.len() {
-            if let Expr::Var(name) = args[i].clone()
-                && name == var
+    fn find_var(&self, var: String, args: &[Expr]) -> bool {
+        for arg in args {
+            if let Expr::Var(name) = arg
+                && *name == var
             {
                 return true;
             }
@@ -18,13 +19,100 @@ impl Expr {
         false
     }
 
+    /// 对调用 `name(call_args)` 应用多元链式法则：
+    /// d/dx f(g1, ..., gn) = Σ ∂f/∂pi (g1, ..., gn) * dgi/dx
+    fn derivative_call(
+        &self,
+        name: &str,
+        call_args: &[Expr],
+        dx: &String,
+        args: &[Expr],
+        function_table: Rc<RefCell<FunctionTable>>,
+        mode: DerivativeMode,
+    ) -> Result<Expr, CalcError> {
+        let callee = match function_table.borrow().find(name, call_args.len()) {
+            Some(func) => func.clone(),
+            None => {
+                return Err(CalcError::UnknownFunction {
+                    name: name.to_string(),
+                    arity: call_args.len(),
+                    span: None,
+                });
+            }
+        };
+        let params = match &callee.symble {
+            Expr::Func(_, params) => params.clone(),
+            _ => return Err(CalcError::IllegalFunction),
+        };
+
+        let mut res: Option<Expr> = None;
+        for (param, call_arg) in params.iter().zip(call_args) {
+            let dg = call_arg
+                .derivative(dx, args, function_table.clone(), mode)?
+                .simplify();
+            if dg == Expr::Const(0.0) {
+                continue;
+            }
+
+            let Expr::Var(p) = param else {
+                return Err(CalcError::IllegalFunction);
+            };
+            let df = || -> Result<Expr, CalcError> {
+                Ok(callee
+                    .body
+                    .derivative(p, &params, function_table.clone(), mode)?
+                    .simplify())
+            };
+
+            let outer = match mode {
+                DerivativeMode::Inline => df()?.substitute(&params, call_args),
+                DerivativeMode::Symbolic => {
+                    // 把偏导数记为 f_x 等函数，调用处只保留符号引用；它和 partial 的结果
+                    // 同名并缓存在表中，命中缓存时不再重复求导，原函数被修改时随之失效
+                    let vars = vec![p.clone()];
+                    let cached = function_table
+                        .borrow()
+                        .cached_partial(&callee.key(), &vars)
+                        .map(|f| f.name().to_string());
+                    let name = match cached {
+                        Some(name) => name,
+                        None => {
+                            let partial = Function {
+                                symble: Expr::Func(
+                                    callee.mixed_partial_name(&vars),
+                                    params.clone(),
+                                ),
+                                body: df()?,
+                            };
+                            let name = partial.name().to_string();
+                            function_table
+                                .borrow_mut()
+                                .cache_partial(callee.key(), vars, partial)?;
+                            name
+                        }
+                    };
+                    Expr::Func(name, call_args.to_vec())
+                }
+            };
+
+            let term = Expr::Mul(Box::new(outer), Box::new(dg));
+            res = Some(match res {
+                Some(sum) => Expr::Add(Box::new(sum), Box::new(term)),
+                None => term,
+            });
+        }
+
+        Ok(res.unwrap_or(Expr::Const(0.0)))
+    }
+
     /// 对表达式按变量 `dx` 求导。
-    fn derivative(
+    pub(super) fn derivative(
         &self,
         dx: &String,
-        args: &Vec<Expr>,
+        args: &[Expr],
         function_table: Rc<RefCell<FunctionTable>>,
-    ) -> Result<Expr, anyhow::Error> {
+        mode: DerivativeMode,
+    ) -> Result<Expr, CalcError> {
         match self {
             Expr::Const(_) => Ok(Expr::Const(0.0)),
             Expr::Var(x) => {
@@ -35,25 +123,25 @@ impl Expr {
                         Ok(Expr::Const(0.0))
                     }
                 } else {
-                    Err(anyhow::Error::msg(format!("unknown var: {}", x)))
+                    Err(CalcError::UnknownVariable(x.to_string()))
                 }
             }
             Expr::Add(x, y) => {
-                let l = x.as_ref().derivative(dx, args, function_table.clone())?;
-                let r = y.as_ref().derivative(dx, args, function_table.clone())?;
+                let l = x.as_ref().derivative(dx, args, function_table.clone(), mode)?;
+                let r = y.as_ref().derivative(dx, args, function_table.clone(), mode)?;
                 Ok(Expr::Add(Box::new(l), Box::new(r)))
             }
             Expr::Sub(x, y) => {
-                let l = x.as_ref().derivative(dx, args, function_table.clone())?;
-                let r = y.as_ref().derivative(dx, args, function_table.clone())?;
+                let l = x.as_ref().derivative(dx, args, function_table.clone(), mode)?;
+                let r = y.as_ref().derivative(dx, args, function_table.clone(), mode)?;
                 Ok(Expr::Sub(Box::new(l), Box::new(r)))
             }
             Expr::Mul(x, y) => {
                 // (u * v)' = u' * v + u * v'
                 let u = x.as_ref();
                 let v = y.as_ref();
-                let du = u.derivative(dx, args, function_table.clone())?;
-                let dv = v.derivative(dx, args, function_table.clone())?;
+                let du = u.derivative(dx, args, function_table.clone(), mode)?;
+                let dv = v.derivative(dx, args, function_table.clone(), mode)?;
                 let term1 = Expr::Mul(Box::new(du), Box::new(v.clone()));
                 let term2 = Expr::Mul(Box::new(u.clone()), Box::new(dv));
                 Ok(Expr::Add(Box::new(term1), Box::new(term2)))
@@ -62,8 +150,8 @@ impl Expr {
                 // (u / v)' = (u' * v - u * v') / v^2
                 let u = x.as_ref();
                 let v = y.as_ref();
-                let du = u.derivative(dx, args, function_table.clone())?;
-                let dv = v.derivative(dx, args, function_table.clone())?;
+                let du = u.derivative(dx, args, function_table.clone(), mode)?;
+                let dv = v.derivative(dx, args, function_table.clone(), mode)?;
                 let num_left = Expr::Mul(Box::new(du), Box::new(v.clone()));
                 let num_right = Expr::Mul(Box::new(u.clone()), Box::new(dv));
                 let numerator = Expr::Sub(Box::new(num_left), Box::new(num_right));
@@ -79,8 +167,8 @@ impl Expr {
                     return Ok(Expr::Const(0.0));
                 }
 
-                let du = u.derivative(dx, args, function_table.clone())?;
-                let dv = v.derivative(dx, args, function_table.clone())?;
+                let du = u.derivative(dx, args, function_table.clone(), mode)?;
+                let dv = v.derivative(dx, args, function_table.clone(), mode)?;
 
                 match (u, v) {
                     // f(x)^c,  c 为常数: (u^c)' = c * u^{c-1} * u'
@@ -93,8 +181,8 @@ impl Expr {
                     (Expr::Const(a), _) => {
                         let pow = Expr::Power(Box::new(Expr::Const(*a)), Box::new(v.clone()));
                         let ln_a = Expr::Log(
-                            Box::new(Expr::Const(*a)),
                             Box::new(Expr::Const(std::f64::consts::E)),
+                            Box::new(Expr::Const(*a)),
                         );
                         let inner = Expr::Mul(Box::new(ln_a), Box::new(dv));
                         Ok(Expr::Mul(Box::new(pow), Box::new(inner)))
@@ -103,8 +191,8 @@ impl Expr {
                     (_, _) => {
                         let pow = Expr::Power(Box::new(u.clone()), Box::new(v.clone()));
                         let ln_u = Expr::Log(
-                            Box::new(u.clone()),
                             Box::new(Expr::Const(std::f64::consts::E)),
+                            Box::new(u.clone()),
                         );
                         let term1 = Expr::Mul(Box::new(dv), Box::new(ln_u));
                         let u_div = Expr::Div(Box::new(du), Box::new(u.clone()));
@@ -114,6 +202,11 @@ impl Expr {
                     }
                 }
             }
+            Expr::Neg(x) => {
+                // (-u)' = -u'
+                let du = x.as_ref().derivative(dx, args, function_table.clone(), mode)?;
+                Ok(Expr::Neg(Box::new(du)))
+            }
             Expr::Log(x, y) => {
                 // log_u(v) = ln(v) / ln(u)
                 let u = x.as_ref();
@@ -124,18 +217,18 @@ impl Expr {
                     return Ok(Expr::Const(0.0));
                 }
 
-                let du = u.derivative(dx, args, function_table.clone())?;
-                let dv = v.derivative(dx, args, function_table.clone())?;
+                let du = u.derivative(dx, args, function_table.clone(), mode)?;
+                let dv = v.derivative(dx, args, function_table.clone(), mode)?;
 
                 // A = ln v, B = ln u
                 // (A/B)' = (A' * B - A * B') / B^2
                 let ln_v = Expr::Log(
-                    Box::new(v.clone()),
                     Box::new(Expr::Const(std::f64::consts::E)),
+                    Box::new(v.clone()),
                 );
                 let ln_u = Expr::Log(
-                    Box::new(u.clone()),
                     Box::new(Expr::Const(std::f64::consts::E)),
+                    Box::new(u.clone()),
                 );
 
                 // A' = v'/v
@@ -152,7 +245,7 @@ impl Expr {
             }
             Expr::Trifuncs(name, pvar) => {
                 let inner = pvar.as_ref();
-                let din = inner.derivative(dx, args, function_table.clone())?;
+                let din = inner.derivative(dx, args, function_table.clone(), mode)?;
 
                 match name.as_str() {
                     // (sin g)' = cos(g) * g'
@@ -196,7 +289,7 @@ impl Expr {
                     // (csc g)' = -csc(g) * cot(g) * g'
                     "csc" => {
                         let csc_g = Expr::Trifuncs("csc".to_string(), Box::new(inner.clone()));
-                        let cot_g = Expr::Trifuncs("sot".to_string(), Box::new(inner.clone()));
+                        let cot_g = Expr::Trifuncs("cot".to_string(), Box::new(inner.clone()));
                         let prod = Expr::Mul(Box::new(csc_g), Box::new(cot_g));
                         let prod = Expr::Mul(Box::new(prod), Box::new(din));
                         Ok(Expr::Mul(Box::new(Expr::Const(-1.0)), Box::new(prod)))
@@ -215,39 +308,122 @@ impl Expr {
                         let prod = Expr::Mul(Box::new(csc_sq), Box::new(din));
                         Ok(Expr::Mul(Box::new(Expr::Const(-1.0)), Box::new(prod)))
                     }
-                    _ => Err(anyhow::Error::msg(format!(
-                        "unknown trigonometric function for derivative: {}",
-                        name
-                    ))),
+                    _ => Err(CalcError::UnsupportedDerivative(self.to_string())),
                 }
             }
-            Expr::Func(_, _) | Expr::Equal(_, _) => Err(anyhow::Error::msg(
-                "derivative for this expression type is not implemented",
-            )),
+            Expr::Func(name, call_args) => {
+                self.derivative_call(name, call_args, dx, args, function_table, mode)
+            }
+            Expr::Equal(_, _) => Err(CalcError::UnsupportedDerivative(self.to_string())),
         }
     }
 }
 
 impl Function {
-    pub(crate) fn derivative(
+    /// 依次对 `vars` 中的变量求偏导得到的函数名，例如 `[x, x, y]` 得到 `f_xxy`。
+    ///
+    /// 分隔方式由全部参数名决定，保证同一函数不同的求导序列得到不同的名字：
+    /// 参数名不都是单个字符时用 '_' 分隔，例如 `f_x1_y`；参数名本身含有 '_' 时
+    /// 改用从 1 开始的参数序号，例如 `f_1_2`。
+    fn mixed_partial_name(&self, vars: &[String]) -> String {
+        let Expr::Func(name, params) = &self.symble else {
+            return String::new();
+        };
+        let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+
+        let parts: Vec<String> = if params.iter().any(|p| p.contains('_')) {
+            vars.iter()
+                .map(|v| match params.iter().position(|p| p == v) {
+                    Some(i) => (i + 1).to_string(),
+                    None => v.clone(),
+                })
+                .collect()
+        } else {
+            vars.to_vec()
+        };
+        let sep = if params.iter().all(|p| p.chars().count() == 1) {
+            ""
+        } else {
+            "_"
+        };
+        format!("{}_{}", name, parts.join(sep))
+    }
+
+    /// 依次对 `vars` 中的变量求偏导，例如 `[x, x, y]` 得到 ∂³f/∂y∂x² 并命名为 `f_xxy`。
+    ///
+    /// 每一步的中间结果都会缓存在 `function_table` 中，计算 f_xx、f_xy
+    /// 时会复用已经求出的 f_x。
+    pub fn partial_derivative(
+        &self,
+        vars: &[String],
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<Function, CalcError> {
+        let params = match &self.symble {
+            Expr::Func(_, params) => params.clone(),
+            _ => return Err(CalcError::IllegalFunction),
+        };
+        for var in vars {
+            if !params.contains(&Expr::Var(var.clone())) {
+                return Err(CalcError::UnknownVariable(var.to_string()));
+            }
+        }
+
+        let key = self.key();
+        let mut current = self.clone();
+
+        for k in 1..=vars.len() {
+            let prefix = &vars[..k];
+            if let Some(cached) = function_table.borrow().cached_partial(&key, prefix) {
+                current = cached.clone();
+                continue;
+            }
+
+            let mut next = current.derivative(&vars[k - 1], function_table.clone())?;
+            next.symble = Expr::Func(self.mixed_partial_name(prefix), params.clone());
+
+            function_table
+                .borrow_mut()
+                .cache_partial(key.clone(), prefix.to_vec(), next.clone())?;
+            current = next;
+        }
+
+        Ok(current)
+    }
+
+    pub fn derivative(
+        &self,
+        dx: &String,
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<Function, CalcError> {
+        self.derivative_with_mode(dx, function_table, DerivativeMode::Inline)
+    }
+
+    /// 按变量 `dx` 求导，`mode` 决定对函数调用如何应用链式法则。
+    ///
+    /// 单参数函数对该参数求导的结果命名为 `f'`，其余情况与 `partial` 相同，例如 `f_y`。
+    pub fn derivative_with_mode(
         &self,
         dx: &String,
         function_table: Rc<RefCell<FunctionTable>>,
-    ) -> Result<Function, anyhow::Error> {
-        if let Expr::Func(mut name, args) = self.symble.clone() {
+        mode: DerivativeMode,
+    ) -> Result<Function, CalcError> {
+        if let Expr::Func(name, args) = self.symble.clone() {
             let body = self
                 .body
-                .derivative(dx, &args, function_table.clone())?
+                .derivative(dx, &args, function_table.clone(), mode)?
                 .simplify();
-            name.push('\'');
+            // 对唯一的参数求导时记为 f'，否则按变量命名为 f_y 等，
+            // 对不同变量求出的导数不会写到同一个名字上
+            let name = match args.as_slice() {
+                [Expr::Var(p)] if p == dx => format!("{}'", name),
+                _ => self.mixed_partial_name(std::slice::from_ref(dx)),
+            };
             Ok(Function {
                 symble: Expr::Func(name, args),
-                body: body,
-                tokens: Vec::new(),
-                i: 0,
+                body,
             })
         } else {
-            Err(anyhow::Error::msg("illegal function"))
+            Err(CalcError::IllegalFunction)
         }
     }
 }
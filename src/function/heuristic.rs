@@ -0,0 +1,811 @@
+use std::{cell::RefCell, f64::consts::E, rc::Rc};
+
+use crate::{
+    expr::Expr,
+    function::{DerivativeMode, Function, FunctionTable},
+};
+
+/// 启发式积分时代换变量的名字，词法分析器不会产生以 '_' 开头的标识符，因此不会与参数冲突。
+const SUBST_VAR: &str = "_u";
+
+/// 用于数值验证的采样点。
+const SAMPLES: [f64; 6] = [0.37, 0.83, 1.41, 2.29, 3.7, 5.3];
+
+fn add(a: Expr, b: Expr) -> Expr {
+    Expr::Add(Box::new(a), Box::new(b))
+}
+
+fn mul(a: Expr, b: Expr) -> Expr {
+    Expr::Mul(Box::new(a), Box::new(b))
+}
+
+fn pow(a: Expr, b: Expr) -> Expr {
+    Expr::Power(Box::new(a), Box::new(b))
+}
+
+fn ln(a: Expr) -> Expr {
+    Expr::Log(Box::new(Expr::Const(E)), Box::new(a))
+}
+
+/// 与整数的差相对于同一组系数的量级 `scale` 可以忽略时取整，避免输出 0.9999999999 这样的常数；
+/// 相对于 `scale` 不可忽略的小系数（例如与 1 并列的 1e-9）保持原值。
+fn clean(v: f64, scale: f64) -> f64 {
+    if (v - v.round()).abs() <= 1e-12 * scale.abs() {
+        v.round()
+    } else {
+        v
+    }
+}
+
+/// 把表达式在 `args` 上取值，`point` 与 `args` 一一对应；出错或结果非有限时返回 None。
+fn eval_at(
+    expr: &Expr,
+    args: &[Expr],
+    point: &[f64],
+    function_table: Rc<RefCell<FunctionTable>>,
+) -> Option<f64> {
+    let mut func = Function::new_with_expr(expr.clone());
+    func.symble = Expr::Func("".to_string(), args.to_vec());
+    func.caculate(point, function_table)
+        .ok()
+        .filter(|v| v.is_finite())
+}
+
+/// 生成采样点：第 `k` 个点上 `dx` 取 SAMPLES[k]，其他参数取 `others` 给出的值。
+fn sample_point(args: &[Expr], dx: &str, x: f64, others: f64) -> Vec<f64> {
+    args.iter()
+        .enumerate()
+        .map(|(i, a)| match a {
+            Expr::Var(name) if name == dx => x,
+            _ => others + 0.29 * i as f64,
+        })
+        .collect()
+}
+
+/// 对 `anti` 求导，并在若干采样点上与 `integrand` 比较。
+pub(super) fn verify(
+    integrand: &Expr,
+    anti: &Expr,
+    dx: &String,
+    args: &[Expr],
+    function_table: Rc<RefCell<FunctionTable>>,
+) -> bool {
+    let Ok(back) = anti.derivative(dx, args, function_table.clone(), DerivativeMode::Inline) else {
+        return false;
+    };
+    let back = back.simplify();
+
+    let mut values = Vec::new();
+    for x in SAMPLES {
+        let point = sample_point(args, dx, x, 0.61);
+        let expected = eval_at(integrand, args, &point, function_table.clone());
+        let got = eval_at(&back, args, &point, function_table.clone());
+        if let (Some(a), Some(b)) = (expected, got) {
+            values.push((a, b));
+        }
+    }
+
+    // 误差按被积函数在采样点上的量级衡量，丢掉很小但不为零的项也会被发现
+    let scale = values.iter().fold(0.0, |m: f64, (a, _)| m.max(a.abs()));
+    values.len() >= 2 && values.iter().all(|(a, b)| (a - b).abs() <= 1e-10 * scale)
+}
+
+impl Expr {
+    /// 把用户自定义函数的调用展开为函数体。
+    fn inline_calls(&self, function_table: Rc<RefCell<FunctionTable>>) -> Option<Expr> {
+        let mut res = self.clone();
+        // 每轮展开一层调用；函数定义不允许循环，因此一定会结束
+        while let Some((name, argc)) = res.called_functions().into_iter().next() {
+            let callee = function_table.borrow().find(&name, argc)?.clone();
+            let Expr::Func(_, params) = &callee.symble else {
+                return None;
+            };
+            res = res.replace_call(&name, params, &callee.body);
+        }
+        Some(res)
+    }
+
+    fn replace_call(&self, name: &str, params: &[Expr], body: &Expr) -> Expr {
+        self.map_children(&|e| match e {
+            Expr::Func(n, args) if n == name && args.len() == params.len() => {
+                let args: Vec<Expr> = args
+                    .iter()
+                    .map(|a| a.replace_call(name, params, body))
+                    .collect();
+                Some(body.substitute(params, &args))
+            }
+            _ => None,
+        })
+    }
+
+    /// 把与 `target` 相同的子表达式替换为 `with`。
+    fn replace_subexpr(&self, target: &Expr, with: &Expr) -> Expr {
+        self.map_children(&|e| {
+            if e == target {
+                Some(with.clone())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// 自顶向下改写表达式：`f` 返回 Some 时用其结果替换该节点，否则继续处理子节点。
+    fn map_children(&self, f: &dyn Fn(&Expr) -> Option<Expr>) -> Expr {
+        if let Some(res) = f(self) {
+            return res;
+        }
+        let g = |e: &Expr| Box::new(e.map_children(f));
+        match self {
+            Expr::Const(_) | Expr::Var(_) => self.clone(),
+            Expr::Func(name, args) => Expr::Func(
+                name.clone(),
+                args.iter().map(|a| a.map_children(f)).collect(),
+            ),
+            Expr::Add(x, y) => Expr::Add(g(x), g(y)),
+            Expr::Sub(x, y) => Expr::Sub(g(x), g(y)),
+            Expr::Mul(x, y) => Expr::Mul(g(x), g(y)),
+            Expr::Div(x, y) => Expr::Div(g(x), g(y)),
+            Expr::Power(x, y) => Expr::Power(g(x), g(y)),
+            Expr::Log(x, y) => Expr::Log(g(x), g(y)),
+            Expr::Equal(x, y) => Expr::Equal(g(x), g(y)),
+            Expr::Neg(x) => Expr::Neg(g(x)),
+            Expr::Trifuncs(name, x) => Expr::Trifuncs(name.clone(), g(x)),
+        }
+    }
+
+    /// 把乘积展开为因子列表，除法 a/b 记为 a * b^-1。
+    fn factors(&self) -> Vec<Expr> {
+        match self {
+            Expr::Mul(x, y) => {
+                let mut res = x.factors();
+                res.extend(y.factors());
+                res
+            }
+            Expr::Div(x, y) => {
+                let mut res = x.factors();
+                for f in y.factors() {
+                    res.push(match f {
+                        Expr::Power(u, k) => pow(*u, Expr::Neg(k).simplify()),
+                        f => pow(f, Expr::Const(-1.0)),
+                    });
+                }
+                res
+            }
+            Expr::Neg(x) => {
+                let mut res = vec![Expr::Const(-1.0)];
+                res.extend(x.factors());
+                res
+            }
+            _ => vec![self.clone()],
+        }
+    }
+
+    /// LIATE 顺序：对数、反三角、代数、三角、指数，越小越适合作为分部积分中的 u。
+    fn liate_rank(&self, dx: &str) -> u8 {
+        match self {
+            Expr::Log(_, _) => 0,
+            Expr::Trifuncs(name, _) if name.starts_with("arc") => 1,
+            Expr::Var(_) => 2,
+            Expr::Power(_, k) if !k.contains_var(dx) => 2,
+            Expr::Trifuncs(_, _) => 3,
+            Expr::Power(c, _) if !c.contains_var(dx) => 4,
+            _ => 5,
+        }
+    }
+
+    /// 依次尝试部分分式、换元积分和分部积分，结果都经过数值验证。
+    pub(super) fn heuristic_integral(
+        &self,
+        dx: &String,
+        args: &[Expr],
+        function_table: Rc<RefCell<FunctionTable>>,
+        depth: usize,
+    ) -> Option<Expr> {
+        let integrand = self.inline_calls(function_table.clone())?.simplify();
+
+        let strategies: [&dyn Fn() -> Option<Expr>; 3] = [
+            &|| integrand.partial_fractions(dx, &function_table),
+            &|| integrand.substitution(dx, args, function_table.clone(), depth),
+            &|| integrand.by_parts(dx, args, function_table.clone(), depth),
+        ];
+        for strategy in strategies {
+            if let Some(anti) = strategy()
+                && verify(&integrand, &anti, dx, args, function_table.clone())
+            {
+                return Some(anti);
+            }
+        }
+
+        None
+    }
+
+    /// 换元积分：寻找 c * g'(x) * F(g(x)) 形式的被积函数，∫ = c * G(g(x))，其中 G' = F。
+    fn substitution(
+        &self,
+        dx: &String,
+        args: &[Expr],
+        function_table: Rc<RefCell<FunctionTable>>,
+        depth: usize,
+    ) -> Option<Expr> {
+        let factors = self.factors();
+        let u = Expr::Var(SUBST_VAR.to_string());
+        let mut sub_args = args.to_vec();
+        sub_args.push(u.clone());
+
+        for (i, outer) in factors.iter().enumerate() {
+            // 候选的内层函数 g：因子本身，以及它的自变量
+            let mut candidates = vec![outer.clone()];
+            match outer {
+                Expr::Trifuncs(_, g) => candidates.push(g.as_ref().clone()),
+                Expr::Power(g, k) if !k.contains_var(dx) => candidates.push(g.as_ref().clone()),
+                Expr::Power(c, g) if !c.contains_var(dx) => candidates.push(g.as_ref().clone()),
+                Expr::Log(b, g) if !b.contains_var(dx) => candidates.push(g.as_ref().clone()),
+                _ => {}
+            }
+
+            let rest = factors
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, f)| f.clone())
+                .reduce(mul)
+                .unwrap_or(Expr::Const(1.0));
+
+            for g in candidates {
+                let Ok(dg) = g.derivative(dx, args, function_table.clone(), DerivativeMode::Inline)
+                else {
+                    continue;
+                };
+                let dg = dg.simplify();
+                // g 为线性函数的情形由基本积分规则处理
+                if !dg.contains_var(dx) {
+                    continue;
+                }
+
+                let Some(c) = Self::constant_ratio(&rest, &dg, dx, args, function_table.clone())
+                else {
+                    continue;
+                };
+
+                let f_u = outer.replace_subexpr(&g, &u);
+                if f_u.contains_var(dx) {
+                    continue;
+                }
+                let Ok(anti_u) = f_u.integral_depth(
+                    &SUBST_VAR.to_string(),
+                    &sub_args,
+                    function_table.clone(),
+                    depth,
+                ) else {
+                    continue;
+                };
+
+                return Some(mul(c, anti_u.substitute(std::slice::from_ref(&u), &[g])));
+            }
+        }
+
+        None
+    }
+
+    /// 若 `num / den` 不随 `dx` 变化，返回这个比值。
+    fn constant_ratio(
+        num: &Expr,
+        den: &Expr,
+        dx: &str,
+        args: &[Expr],
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Option<Expr> {
+        let ratios = |others: f64| -> Vec<f64> {
+            SAMPLES
+                .iter()
+                .filter_map(|x| {
+                    let point = sample_point(args, dx, *x, others);
+                    let n = eval_at(num, args, &point, function_table.clone())?;
+                    let d = eval_at(den, args, &point, function_table.clone())?;
+                    if d.abs() < 1e-12 { None } else { Some(n / d) }
+                })
+                .collect()
+        };
+        let same = |vs: &[f64]| {
+            vs.len() >= 2
+                && vs
+                    .iter()
+                    .all(|v| (v - vs[0]).abs() <= 1e-9 * (1.0 + vs[0].abs()))
+        };
+
+        let first = ratios(0.61);
+        if !same(&first) {
+            return None;
+        }
+
+        // 比值与其他参数也无关时直接取数值，否则保留符号形式
+        let second = ratios(1.73);
+        if same(&second) && (second[0] - first[0]).abs() <= 1e-9 * (1.0 + first[0].abs()) {
+            Some(Expr::Const(clean(first[0], first[0])))
+        } else {
+            Some(Expr::Div(Box::new(num.clone()), Box::new(den.clone())).simplify())
+        }
+    }
+
+    /// 分部积分 ∫u dv = u*v - ∫v du，按 LIATE 顺序选择 u。
+    fn by_parts(
+        &self,
+        dx: &String,
+        args: &[Expr],
+        function_table: Rc<RefCell<FunctionTable>>,
+        depth: usize,
+    ) -> Option<Expr> {
+        let factors: Vec<Expr> = self.factors();
+        let (consts, vars): (Vec<Expr>, Vec<Expr>) =
+            factors.into_iter().partition(|f| !f.contains_var(dx));
+
+        let (ui, _) = vars
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, f)| f.liate_rank(dx))?;
+        let u = vars[ui].clone();
+        // 只有一个因子时，只对对数和反三角函数使用 dv = dx
+        if vars.len() == 1 && u.liate_rank(dx) > 1 {
+            return None;
+        }
+
+        let dv = vars
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != ui)
+            .map(|(_, f)| f.clone())
+            .chain(consts)
+            .reduce(mul)
+            .unwrap_or(Expr::Const(1.0));
+
+        let v = dv
+            .integral_depth(dx, args, function_table.clone(), depth)
+            .ok()?
+            .simplify();
+        let du = u
+            .derivative(dx, args, function_table.clone(), DerivativeMode::Inline)
+            .ok()?
+            .simplify();
+        let rest = mul(v.clone(), du)
+            .simplify()
+            .integral_depth(dx, args, function_table.clone(), depth)
+            .ok()?;
+
+        Some(Expr::Sub(Box::new(mul(u, v)), Box::new(rest)))
+    }
+
+    /// 有理函数 P(x)/Q(x) 的部分分式分解。
+    fn partial_fractions(
+        &self,
+        dx: &str,
+        function_table: &Rc<RefCell<FunctionTable>>,
+    ) -> Option<Expr> {
+        let (p, q) = self.to_rational(dx, function_table)?;
+        let (quot, rem) = poly::divmod(&p, &q)?;
+        let x = Expr::Var(dx.to_string());
+
+        let mut terms = Vec::new();
+
+        // 多项式部分逐项积分
+        let scale = quot.iter().fold(0.0, |m: f64, c| m.max(c.abs()));
+        for (k, c) in quot.iter().enumerate() {
+            let c = clean(*c, scale);
+            if c != 0.0 {
+                let k1 = (k + 1) as f64;
+                terms.push(mul(Expr::Const(c / k1), pow(x.clone(), Expr::Const(k1))));
+            }
+        }
+
+        if poly::degree(&rem).is_some() {
+            terms.extend(poly::integrate_proper(&rem, &q, &x)?);
+        }
+
+        Some(terms.into_iter().reduce(add).unwrap_or(Expr::Const(0.0)))
+    }
+
+    /// 若表达式是 `dx` 的有理函数且系数都是数字，返回 (分子, 分母) 多项式系数（从低次到高次）。
+    fn to_rational(
+        &self,
+        dx: &str,
+        function_table: &Rc<RefCell<FunctionTable>>,
+    ) -> Option<(Vec<f64>, Vec<f64>)> {
+        match self {
+            Expr::Const(c) => Some((vec![*c], vec![1.0])),
+            // 不含 dx 的子表达式（如 ln(10)）必须能求出数值
+            _ if !self.contains_var(dx) => {
+                let c = eval_at(self, &[], &Vec::new(), function_table.clone())?;
+                Some((vec![c], vec![1.0]))
+            }
+            Expr::Var(x) if x == dx => Some((vec![0.0, 1.0], vec![1.0])),
+            Expr::Add(x, y) | Expr::Sub(x, y) => {
+                let (a, b) = x.to_rational(dx, function_table)?;
+                let (c, d) = y.to_rational(dx, function_table)?;
+                let ad = poly::mul(&a, &d);
+                let mut cb = poly::mul(&c, &b);
+                if let Expr::Sub(_, _) = self {
+                    cb = poly::scale(&cb, -1.0);
+                }
+                Some((poly::add(&ad, &cb), poly::mul(&b, &d)))
+            }
+            Expr::Mul(x, y) => {
+                let (a, b) = x.to_rational(dx, function_table)?;
+                let (c, d) = y.to_rational(dx, function_table)?;
+                Some((poly::mul(&a, &c), poly::mul(&b, &d)))
+            }
+            Expr::Div(x, y) => {
+                let (a, b) = x.to_rational(dx, function_table)?;
+                let (c, d) = y.to_rational(dx, function_table)?;
+                Some((poly::mul(&a, &d), poly::mul(&b, &c)))
+            }
+            Expr::Neg(x) => {
+                let (a, b) = x.to_rational(dx, function_table)?;
+                Some((poly::scale(&a, -1.0), b))
+            }
+            Expr::Power(x, k) => {
+                let Expr::Const(k) = k.as_ref() else {
+                    return None;
+                };
+                if k.fract() != 0.0 || k.abs() > 16.0 {
+                    return None;
+                }
+                let (a, b) = x.to_rational(dx, function_table)?;
+                let (mut num, mut den) = (vec![1.0], vec![1.0]);
+                for _ in 0..(k.abs() as usize) {
+                    num = poly::mul(&num, &a);
+                    den = poly::mul(&den, &b);
+                }
+                if *k < 0.0 {
+                    Some((den, num))
+                } else {
+                    Some((num, den))
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// 部分分式分解所需的多项式与复数运算，多项式系数从低次到高次排列。
+mod poly {
+    use super::{clean, ln, pow};
+    use crate::expr::Expr;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Complex {
+        re: f64,
+        im: f64,
+    }
+
+    impl Complex {
+        fn new(re: f64, im: f64) -> Complex {
+            Complex { re, im }
+        }
+
+        fn add(self, o: Complex) -> Complex {
+            Complex::new(self.re + o.re, self.im + o.im)
+        }
+
+        fn sub(self, o: Complex) -> Complex {
+            Complex::new(self.re - o.re, self.im - o.im)
+        }
+
+        fn mul(self, o: Complex) -> Complex {
+            Complex::new(
+                self.re * o.re - self.im * o.im,
+                self.re * o.im + self.im * o.re,
+            )
+        }
+
+        fn div(self, o: Complex) -> Complex {
+            let d = o.re * o.re + o.im * o.im;
+            Complex::new(
+                (self.re * o.re + self.im * o.im) / d,
+                (self.im * o.re - self.re * o.im) / d,
+            )
+        }
+
+        fn abs(self) -> f64 {
+            self.re.hypot(self.im)
+        }
+    }
+
+    pub(super) fn degree(p: &[f64]) -> Option<usize> {
+        p.iter().rposition(|c| c.abs() > 1e-12)
+    }
+
+    fn trim(p: &[f64]) -> Vec<f64> {
+        match degree(p) {
+            Some(d) => p[..=d].to_vec(),
+            None => vec![0.0],
+        }
+    }
+
+    pub(super) fn add(a: &[f64], b: &[f64]) -> Vec<f64> {
+        let mut res = vec![0.0; a.len().max(b.len())];
+        for (i, c) in a.iter().enumerate() {
+            res[i] += c;
+        }
+        for (i, c) in b.iter().enumerate() {
+            res[i] += c;
+        }
+        trim(&res)
+    }
+
+    pub(super) fn scale(a: &[f64], k: f64) -> Vec<f64> {
+        a.iter().map(|c| c * k).collect()
+    }
+
+    pub(super) fn mul(a: &[f64], b: &[f64]) -> Vec<f64> {
+        let mut res = vec![0.0; a.len() + b.len() - 1];
+        for (i, x) in a.iter().enumerate() {
+            for (j, y) in b.iter().enumerate() {
+                res[i + j] += x * y;
+            }
+        }
+        trim(&res)
+    }
+
+    /// 多项式带余除法，返回 (商, 余式)。
+    pub(super) fn divmod(p: &[f64], q: &[f64]) -> Option<(Vec<f64>, Vec<f64>)> {
+        let dq = degree(q)?;
+        let mut rem = trim(p);
+        let mut quot = vec![0.0; rem.len().saturating_sub(dq).max(1)];
+
+        while let Some(dr) = degree(&rem) {
+            if dr < dq {
+                break;
+            }
+            let c = rem[dr] / q[dq];
+            quot[dr - dq] = c;
+            for (i, qc) in q[..=dq].iter().enumerate() {
+                rem[dr - dq + i] -= c * qc;
+            }
+            rem[dr] = 0.0;
+        }
+
+        Some((trim(&quot), trim(&rem)))
+    }
+
+    fn eval(p: &[f64], x: Complex) -> Complex {
+        p.iter().rev().fold(Complex::new(0.0, 0.0), |acc, c| {
+            acc.mul(x).add(Complex::new(*c, 0.0))
+        })
+    }
+
+    fn derivative(p: &[f64]) -> Vec<f64> {
+        if p.len() <= 1 {
+            return vec![0.0];
+        }
+        p.iter()
+            .enumerate()
+            .skip(1)
+            .map(|(k, c)| c * k as f64)
+            .collect()
+    }
+
+    /// Durand–Kerner 迭代求首一多项式的全部复根。
+    fn roots(monic: &[f64]) -> Vec<Complex> {
+        let n = monic.len() - 1;
+        let bound = 1.0 + monic[..n].iter().fold(0.0f64, |m, c| m.max(c.abs()));
+        let seed = Complex::new(0.4, 0.9);
+        let mut zs: Vec<Complex> = (0..n)
+            .scan(Complex::new(bound, 0.0), |z, _| {
+                *z = z.mul(seed);
+                Some(*z)
+            })
+            .collect();
+
+        for _ in 0..2000 {
+            let mut delta = 0.0f64;
+            for i in 0..n {
+                let mut den = Complex::new(1.0, 0.0);
+                for j in 0..n {
+                    if i != j {
+                        den = den.mul(zs[i].sub(zs[j]));
+                    }
+                }
+                let step = eval(monic, zs[i]).div(den);
+                if step.re.is_finite() && step.im.is_finite() {
+                    zs[i] = zs[i].sub(step);
+                    delta = delta.max(step.abs());
+                }
+            }
+            if delta < 1e-15 {
+                break;
+            }
+        }
+
+        zs
+    }
+
+    /// 把数值根按距离聚成带重数的根，并用 Newton 法在 Q^(m-1) 上精修重根。
+    fn cluster_roots(monic: &[f64]) -> Vec<(Complex, usize)> {
+        let mut res: Vec<(Complex, usize)> = Vec::new();
+        let mut rest = roots(monic);
+        let scale = rest.iter().fold(0.0, |m: f64, z| m.max(z.abs()));
+
+        while let Some(z) = rest.pop() {
+            let tol = 1e-4 * (1.0 + z.abs());
+            let (same, others): (Vec<Complex>, Vec<Complex>) =
+                rest.into_iter().partition(|w| w.sub(z).abs() < tol);
+            rest = others;
+
+            let m = same.len() + 1;
+            let mut root = same
+                .iter()
+                .fold(z, |acc, w| acc.add(*w))
+                .div(Complex::new(m as f64, 0.0));
+
+            let mut dp = monic.to_vec();
+            for _ in 1..m {
+                dp = derivative(&dp);
+            }
+            let ddp = derivative(&dp);
+            for _ in 0..50 {
+                let d = eval(&ddp, root);
+                if d.abs() < 1e-300 {
+                    break;
+                }
+                root = root.sub(eval(&dp, root).div(d));
+            }
+
+            if root.im.abs() < 1e-8 * (1.0 + root.re.abs()) {
+                root.im = 0.0;
+            }
+            root = Complex::new(clean(root.re, scale), clean(root.im, scale));
+            res.push((root, m));
+        }
+
+        res
+    }
+
+    /// 高斯消元解线性方程组 a * x = b。
+    fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+        let n = b.len();
+        for col in 0..n {
+            let pivot = (col..n).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            b.swap(col, pivot);
+            let pivot_row = a[col].clone();
+            for row in 0..n {
+                if row != col {
+                    let k = a[row][col] / pivot_row[col];
+                    for (v, p) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                        *v -= k * p;
+                    }
+                    b[row] -= k * b[col];
+                }
+            }
+        }
+        Some((0..n).map(|i| b[i] / a[i][i]).collect())
+    }
+
+    /// 对真分式 rem/q 做部分分式分解并逐项积分。
+    ///
+    /// 支持实根（任意重数）和单重的不可约二次因子。
+    pub(super) fn integrate_proper(rem: &[f64], q: &[f64], x: &Expr) -> Option<Vec<Expr>> {
+        let dq = degree(q)?;
+        let lc = q[dq];
+        let monic: Vec<f64> = q[..=dq].iter().map(|c| c / lc).collect();
+
+        // 每一项的分母因子：实根 (x - r)^k 或二次因子 x^2 + p x + s
+        enum Factor {
+            Linear(f64, usize),
+            Quadratic(f64, f64),
+        }
+
+        let mut factors = Vec::new();
+        for (root, m) in cluster_roots(&monic) {
+            if root.im == 0.0 {
+                for k in 1..=m {
+                    factors.push(Factor::Linear(root.re, k));
+                }
+            } else if root.im > 0.0 {
+                if m > 1 {
+                    return None;
+                }
+                factors.push(Factor::Quadratic(
+                    -2.0 * root.re,
+                    root.re * root.re + root.im * root.im,
+                ));
+            }
+        }
+
+        // 每个未知系数对应的多项式：monic / 因子 或 x * monic / 因子
+        let mut basis = Vec::new();
+        for f in &factors {
+            match f {
+                Factor::Linear(r, k) => {
+                    let mut d = vec![1.0];
+                    for _ in 0..*k {
+                        d = mul(&d, &[-r, 1.0]);
+                    }
+                    basis.push(divmod(&monic, &d)?.0);
+                }
+                Factor::Quadratic(p, s) => {
+                    let t = divmod(&monic, &[*s, *p, 1.0])?.0;
+                    basis.push(mul(&t, &[0.0, 1.0]));
+                    basis.push(t);
+                }
+            }
+        }
+        if basis.len() != dq {
+            return None;
+        }
+
+        let a: Vec<Vec<f64>> = (0..dq)
+            .map(|row| basis.iter().map(|b| *b.get(row).unwrap_or(&0.0)).collect())
+            .collect();
+        let b: Vec<f64> = (0..dq).map(|i| rem.get(i).unwrap_or(&0.0) / lc).collect();
+        let coef = solve(a, b)?;
+
+        let shifted = |r: f64| {
+            if r < 0.0 {
+                Expr::Add(Box::new(x.clone()), Box::new(Expr::Const(-r)))
+            } else {
+                Expr::Sub(Box::new(x.clone()), Box::new(Expr::Const(r)))
+            }
+        };
+
+        let scale = coef.iter().fold(0.0, |m: f64, c| m.max(c.abs()));
+        let mut terms = Vec::new();
+        let mut i = 0;
+        for f in &factors {
+            match f {
+                Factor::Linear(r, k) => {
+                    let c = clean(coef[i], scale);
+                    i += 1;
+                    if c == 0.0 {
+                        continue;
+                    }
+                    if *k == 1 {
+                        // ∫c/(x-r) = c*ln(x-r)
+                        terms.push(super::mul(Expr::Const(c), ln(shifted(*r))));
+                    } else {
+                        // ∫c/(x-r)^k = c/(1-k) * (x-r)^(1-k)
+                        let e = 1.0 - *k as f64;
+                        terms.push(super::mul(
+                            Expr::Const(clean(c / e, scale / e.abs())),
+                            pow(shifted(*r), Expr::Const(e)),
+                        ));
+                    }
+                }
+                Factor::Quadratic(p, s) => {
+                    let (bc, cc) = (clean(coef[i], scale), clean(coef[i + 1], scale));
+                    i += 2;
+                    // x^2+px+s = (x-a)^2 + w^2
+                    let a = -p / 2.0;
+                    let w = (s - a * a).sqrt();
+                    let quad = super::add(
+                        pow(x.clone(), Expr::Const(2.0)),
+                        super::add(super::mul(Expr::Const(*p), x.clone()), Expr::Const(*s)),
+                    );
+                    // ∫(Bx+C)/quad = B/2*ln(quad) + (C+B*a)/w * arctan((x-a)/w)
+                    if bc != 0.0 {
+                        terms.push(super::mul(
+                            Expr::Const(clean(bc / 2.0, scale / 2.0)),
+                            ln(quad),
+                        ));
+                    }
+                    let k = clean((cc + bc * a) / w, scale * (1.0 + a.abs()) / w);
+                    if k != 0.0 {
+                        let r = a.hypot(w);
+                        let arg = Expr::Div(
+                            Box::new(shifted(clean(a, r))),
+                            Box::new(Expr::Const(clean(w, r))),
+                        );
+                        terms.push(super::mul(
+                            Expr::Const(k),
+                            Expr::Trifuncs("arctan".to_string(), Box::new(arg)),
+                        ));
+                    }
+                }
+            }
+        }
+
+        Some(terms)
+    }
+}
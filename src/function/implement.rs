@@ -1,178 +1,451 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::{
-    expr::{Expr, Token},
-    function::{Function, FunctionTable},
+    error::CalcError,
+    expr::{Expr, SpannedToken, Token},
+    function::{DefineMode, Function, FunctionKey, FunctionTable},
+    parser::Parser,
 };
 
+/// 失效的缓存偏导数：(原函数, 求导变量序列, 偏导函数)。
+type StalePartial = (FunctionKey, Vec<String>, FunctionKey);
+
 impl Function {
-    pub(super) fn expect(&mut self, token: Token) -> Result<(), anyhow::Error> {
-        if self.i >= self.tokens.len() {
-            return Err(anyhow::Error::msg("index over tokens.len()"));
-        }
+    pub fn new(
+        tokens: &[SpannedToken],
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<Function, CalcError> {
+        Self::new_with_mode(tokens, function_table, DefineMode::Strict)
+    }
+
+    /// 解析函数定义并按 `mode` 写入 `function_table`。
+    pub fn new_with_mode(
+        tokens: &[SpannedToken],
+        function_table: Rc<RefCell<FunctionTable>>,
+        mode: DefineMode,
+    ) -> Result<Function, CalcError> {
+        let res = Self::parse_definition(tokens, &function_table.borrow())?;
+        function_table.borrow_mut().define(res.clone(), mode)?;
 
-        if self.tokens[self.i] == token {
-            self.i += 1;
-            return Ok(());
+        Ok(res)
+    }
+
+    /// 解析 `name(args)=body` 形式的函数定义，只检查调用的函数是否存在，不修改 `function_table`。
+    pub fn parse_definition(
+        tokens: &[SpannedToken],
+        function_table: &FunctionTable,
+    ) -> Result<Function, CalcError> {
+        let mut parser = Parser::new(tokens);
+
+        let symble = parser.parse_signature()?;
+        parser.expect(Token::Operator('='))?;
+        // 签名之后是 '='，其余部分整体作为函数体
+        let body = parser.parse()?;
+
+        // 函数体中调用的函数必须已经定义
+        for (name, argc, span) in parser.calls() {
+            if function_table.find(name, *argc).is_none() {
+                return Err(CalcError::UnknownFunction {
+                    name: name.clone(),
+                    arity: *argc,
+                    span: Some(*span),
+                });
+            }
         }
 
-        let mut err = "expect ".to_string();
-        err.push_str(&token.to_string());
-        err.push_str(" but find ");
-        err.push_str(&self.tokens[self.i].to_string());
+        Ok(Function {
+            symble,
+            body: body.simplify(),
+        })
+    }
 
-        Err(anyhow::Error::msg(err))
+    /// 表示该函数的键 (函数名, 参数个数)。
+    pub fn key(&self) -> FunctionKey {
+        (self.name().to_string(), self.arity())
     }
 
-    pub(super) fn is(&mut self, token: Token) -> Result<bool, anyhow::Error> {
-        if self.i >= self.tokens.len() {
-            return Ok(false);
+    /// 函数名，例如 `f(x,y)=...` 的 `f`。
+    pub fn name(&self) -> &str {
+        match &self.symble {
+            Expr::Func(name, _) => name,
+            _ => "",
         }
+    }
 
-        if self.tokens[self.i] == token {
-            self.i += 1;
-            Ok(true)
-        } else {
-            Ok(false)
+    /// 参数个数。
+    pub fn arity(&self) -> usize {
+        match &self.symble {
+            Expr::Func(_, args) => args.len(),
+            _ => 0,
         }
     }
 
-    fn generate_name(&mut self) -> Result<(), anyhow::Error> {
-        self.i += 1;
+    /// 输出 LaTeX 形式的定义，例如 `f'\left(x\right) = 2x`。
+    pub fn to_latex(&self) -> String {
+        format!("{} = {}", self.symble.to_latex(), self.body.to_latex())
+    }
 
-        self.expect(Token::Operator('('))?;
-        let mut args = Vec::<Expr>::new();
+    pub(super) fn new_with_expr(body: Expr) -> Function {
+        Function {
+            symble: Expr::Func("".to_string(), Vec::new()),
+            body: body.simplify(),
+        }
+    }
+}
 
-        while !self.is(Token::Operator(')'))? {
-            args.push(Expr::Var(self.tokens[self.i].as_identifier()?));
-            self.i += 1;
+impl FunctionTable {
+    pub fn new() -> FunctionTable {
+        FunctionTable {
+            map: HashMap::new(),
+            deps: HashMap::new(),
+            partials: HashMap::new(),
+        }
+    }
 
-            if self.is(Token::Operator(','))? {
-                continue;
-            } else {
-                #[cfg(debug_assertions)]
-                {
-                    println!(
-                        "generate_name: {}",
-                        self.tokens[self.i - 1].as_identifier()?
-                    );
-                }
-            }
+    /// 以 (函数名, 参数个数) 为键插入，同名不同参数个数的函数互不覆盖。
+    /// 已有的同键函数会被直接覆盖，函数体中的调用记录为依赖。
+    pub fn insert(&mut self, func: Function) {
+        let key = func.key();
+        if self.map.get(&key).is_some_and(|old| *old != func) {
+            self.invalidate_partials(&key);
+            // 覆盖的若是缓存的偏导数本身，缓存中的旧结果也不再可用
+            self.partials.retain(|_, partial| partial.key() != key);
         }
 
-        #[cfg(debug_assertions)]
-        {
-            println!("args.len()={}", args.len());
+        let calls: HashSet<FunctionKey> = func.body.called_functions().into_iter().collect();
+        self.deps.insert(key.clone(), calls);
+        self.map.insert(key, func);
+    }
+
+    /// 查找缓存的偏导数 `key` 依次对 `vars` 求导的结果。
+    pub fn cached_partial(&self, key: &FunctionKey, vars: &[String]) -> Option<&Function> {
+        self.partials.get(&(key.clone(), vars.to_vec()))
+    }
+
+    /// 缓存偏导数，同时把它作为普通函数写入表中以便直接调用。
+    ///
+    /// 同名同参的位置上已有不是由 `key` 求导得到的函数（例如用户定义的同名函数）时报错，
+    /// 不会覆盖它。
+    pub fn cache_partial(
+        &mut self,
+        key: FunctionKey,
+        vars: Vec<String>,
+        func: Function,
+    ) -> Result<(), CalcError> {
+        let partial_key = func.key();
+        let entry = (key, vars);
+        let taken = self.contains(&partial_key.0, partial_key.1)
+            && (!self.is_derived_from(&partial_key, &entry.0)
+                || self
+                    .partials
+                    .iter()
+                    .any(|(k, p)| p.key() == partial_key && *k != entry));
+        if taken {
+            return Err(CalcError::Redefinition {
+                name: partial_key.0,
+                arity: partial_key.1,
+            });
         }
 
-        if let Expr::Func(name, _) = &self.symble {
-            self.symble = Expr::Func(name.clone(), args);
-        } else {
-            return Err(anyhow::Error::msg("need Expr::Func"));
+        self.insert(func.clone());
+        self.add_dependency(partial_key, entry.0.clone());
+        self.partials.insert(entry, func);
+        Ok(())
+    }
+
+    /// 写入由 `from` 求导或积分得到的函数并记录依赖。
+    ///
+    /// 同名同参的位置上只能覆盖同样由 `from` 得到的旧结果，已有别的函数时报错。
+    pub fn insert_derived(&mut self, func: Function, from: FunctionKey) -> Result<(), CalcError> {
+        let key = func.key();
+        if self.map.get(&key).is_some_and(|old| *old != func) && !self.is_derived_from(&key, &from)
+        {
+            return Err(CalcError::Redefinition {
+                name: key.0,
+                arity: key.1,
+            });
         }
 
+        self.insert(func);
+        self.add_dependency(key, from);
         Ok(())
     }
 
-    pub(crate) fn new(
-        tokens: &Vec<Token>,
-        function_table: Rc<RefCell<FunctionTable>>,
-    ) -> Result<Function, anyhow::Error> {
-        let name = tokens[0].as_identifier()?;
-        let args = Vec::<Expr>::new();
+    /// `key` 是否由 `from` 求导或积分得到：依赖 `from`，但函数体中没有调用它。
+    fn is_derived_from(&self, key: &FunctionKey, from: &FunctionKey) -> bool {
+        self.deps.get(key).is_some_and(|ds| ds.contains(from))
+            && self
+                .map
+                .get(key)
+                .is_some_and(|f| !f.body.called_functions().contains(from))
+    }
 
-        let mut res = Function {
-            symble: Expr::Func(name.clone(), args),
-            body: Expr::Const(0.0),
+    /// `key` 被修改或删除后会失效的缓存偏导数。
+    fn stale_partials(&self, key: &FunctionKey) -> Vec<StalePartial> {
+        let mut res: Vec<StalePartial> = self
+            .partials
+            .iter()
+            .filter(|((base, _), _)| base == key || self.depends_on(base, key))
+            .map(|((base, vars), func)| (base.clone(), vars.clone(), func.key()))
+            .collect();
+        res.sort();
+        res
+    }
+
+    /// `stale` 中仍被 `stale` 以外的函数直接调用的偏导数。
+    fn partials_in_use<'a>(&self, stale: &'a [StalePartial]) -> Vec<&'a StalePartial> {
+        let partial_keys: HashSet<&FunctionKey> = stale.iter().map(|(_, _, p)| p).collect();
+
+        stale
+            .iter()
+            .filter(|entry| {
+                self.deps
+                    .iter()
+                    .any(|(k, ds)| !partial_keys.contains(k) && ds.contains(&entry.2))
+            })
+            .collect()
+    }
+
+    /// 在 `table` 中按原函数的当前定义重新求出偏导数 `entry`，得到的函数名必须不变；
+    /// 原函数本身也是失效的偏导数时先求出它。
+    fn recompute_partial(
+        table: &Rc<RefCell<FunctionTable>>,
+        entry: &StalePartial,
+        stale: &[StalePartial],
+    ) -> Result<(), CalcError> {
+        let (base, vars, partial) = entry;
+        if !table.borrow().map.contains_key(base)
+            && let Some(parent) = stale.iter().find(|(_, _, p)| p == base)
+        {
+            Self::recompute_partial(table, parent, stale)?;
+        }
 
-            tokens: tokens.to_vec(),
-            i: 0,
+        let Some(func) = table.borrow().find(&base.0, base.1).cloned() else {
+            return Err(CalcError::UnknownFunction {
+                name: base.0.clone(),
+                arity: base.1,
+                span: None,
+            });
         };
+        let key = func.partial_derivative(vars, table.clone())?.key();
+        if key != *partial {
+            return Err(CalcError::InvalidArgument(format!(
+                "{}/{} is still called but would be renamed to {}",
+                partial.0, partial.1, key.0
+            )));
+        }
+        Ok(())
+    }
 
-        res.generate_name()?;
-        res.i+=1;
-        // 目前 generate_body 只处理 '='，主体表达式由 parse_add_or_sub 给出
-        let body = res.parse_add_or_sub(function_table.clone())?;
-        res.body = body.simplify();
+    /// `key` 被修改或删除后，丢弃它以及依赖它的函数的全部缓存偏导数。
+    fn invalidate_partials(&mut self, key: &FunctionKey) {
+        for (base, vars, partial_key) in self.stale_partials(key) {
+            self.partials.remove(&(base, vars));
+            self.deps.remove(&partial_key);
+            self.map.remove(&partial_key);
+        }
+    }
 
-        let mut _binding = function_table.clone();
-        let mut binding = _binding.borrow_mut();
+    /// 按 `mode` 定义函数，返回受影响的（直接或间接依赖它的）函数。
+    ///
+    /// 通过调用依赖它的函数在求值时按名字查找，会自动使用新定义；
+    /// 由它求导得到的函数是旧定义的快照，需要调用方提示用户；
+    /// 缓存的偏导数中仍被其他函数调用的会按新定义重新求出。
+    pub fn define(
+        &mut self,
+        func: Function,
+        mode: DefineMode,
+    ) -> Result<Vec<FunctionKey>, CalcError> {
+        let key = func.key();
 
-        match binding.check_duplicate(&res) {
+        match self.check_duplicate(&func) {
             Ok(true) => {
                 // 已存在同名同参且表达式相同的函数：不必再次插入
+                return Ok(Vec::new());
             }
             Ok(false) => {
                 // 没有同名同参的函数，可以安全插入
-                binding.insert(name.clone(), res.clone());
             }
             Err(e) => {
-                // 存在同名同参但表达式不同，视为冲突
-                return Err(e);
+                // 存在同名同参但表达式不同，除非是替换模式，否则视为冲突
+                if mode == DefineMode::Strict {
+                    return Err(e);
+                }
             }
         }
 
-        Ok(res)
+        // 新函数体调用的函数若（间接）依赖于它自身，替换后将无限递归
+        for callee in func.body.called_functions() {
+            if callee == key || self.depends_on(&callee, &key) {
+                return Err(CalcError::RecursiveDefinition {
+                    name: key.0,
+                    arity: key.1,
+                    callee,
+                });
+            }
+        }
+
+        // 仍被其他函数调用的缓存偏导数按新定义重新求出，否则调用它们的函数将找不到它们；
+        // 无法重新求出时（例如参数改名）返回求导时的错误并拒绝这次定义，表保持不变
+        let stale = self.stale_partials(&key);
+        let in_use = self.partials_in_use(&stale);
+        if in_use.is_empty() {
+            self.insert(func);
+        } else {
+            let table = Rc::new(RefCell::new(self.clone()));
+            table.borrow_mut().insert(func);
+            for entry in in_use {
+                Self::recompute_partial(&table, entry, &stale)?;
+            }
+            *self = table.take();
+        }
+
+        Ok(self.dependents(&key.0, key.1))
     }
 
-    pub(super) fn new_with_expr(body: Expr) -> Function {
-        Function {
-            symble: Expr::Func("".to_string(), Vec::new()),
-            body: body.simplify(),
+    /// 删除函数；若仍有其他函数依赖它则报错并列出这些函数。
+    pub fn delete(&mut self, name: &str, argc: usize) -> Result<Function, CalcError> {
+        if !self.contains(name, argc) {
+            return Err(CalcError::UnknownFunction {
+                name: name.to_string(),
+                arity: argc,
+                span: None,
+            });
+        }
 
-            tokens: Vec::new(),
-            i: 0,
+        let dependents = self.dependents(name, argc);
+        if !dependents.is_empty() {
+            return Err(CalcError::InUse {
+                name: name.to_string(),
+                arity: argc,
+                dependents,
+            });
         }
+
+        self.remove(name, argc)
+            .ok_or_else(|| CalcError::UnknownFunction {
+                name: name.to_string(),
+                arity: argc,
+                span: None,
+            })
     }
-}
 
-impl FunctionTable {
-    pub fn new() -> FunctionTable {
-        FunctionTable {
-            map: HashMap::new(),
-        }
+    /// 记录 `key` 依赖于 `on`，例如导函数依赖于原函数。
+    pub fn add_dependency(&mut self, key: FunctionKey, on: FunctionKey) {
+        self.deps.entry(key).or_default().insert(on);
     }
 
-    pub fn insert(&mut self, name: String, func: Function) {
-        self.map.insert(name, func);
+    /// `name/argc` 直接依赖的函数，按名字排序。
+    pub fn dependencies(&self, name: &str, argc: usize) -> Vec<FunctionKey> {
+        let mut res: Vec<FunctionKey> = self
+            .deps
+            .get(&(name.to_string(), argc))
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        res.sort();
+        res
     }
 
-    pub fn find(&self, name: &str, argc: usize) -> Option<&Function> {
-        if let Some(func) = self.map.get(name) {
-            if let Expr::Func(_, args) = &func.symble {
-                if args.len() == argc {
-                    return Some(func);
+    /// 直接或间接依赖 `name/argc` 的函数，按名字排序。
+    pub fn dependents(&self, name: &str, argc: usize) -> Vec<FunctionKey> {
+        let target = (name.to_string(), argc);
+        let mut res: Vec<FunctionKey> = self
+            .deps
+            .keys()
+            .filter(|k| **k != target && self.depends_on(k, &target))
+            .cloned()
+            .collect();
+        res.sort();
+        res
+    }
+
+    /// `key` 是否直接或间接依赖 `target`。
+    fn depends_on(&self, key: &FunctionKey, target: &FunctionKey) -> bool {
+        let mut stack = vec![key.clone()];
+        let mut visited = HashSet::new();
+
+        while let Some(k) = stack.pop() {
+            if !visited.insert(k.clone()) {
+                continue;
+            }
+            if let Some(ds) = self.deps.get(&k) {
+                for d in ds {
+                    if d == target {
+                        return true;
+                    }
+                    stack.push(d.clone());
                 }
             }
         }
-        None
+
+        false
+    }
+
+    pub fn find(&self, name: &str, argc: usize) -> Option<&Function> {
+        self.map.get(&(name.to_string(), argc))
+    }
+
+    pub fn contains(&self, name: &str, argc: usize) -> bool {
+        self.map.contains_key(&(name.to_string(), argc))
+    }
+
+    pub fn remove(&mut self, name: &str, argc: usize) -> Option<Function> {
+        let key = (name.to_string(), argc);
+        self.invalidate_partials(&key);
+        self.deps.remove(&key);
+        self.map.remove(&key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Function> {
+        self.map.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
     }
 
-    pub fn check_duplicate(&self, func: &Function) -> Result<bool, anyhow::Error> {
+    pub fn check_duplicate(&self, func: &Function) -> Result<bool, CalcError> {
         if let Expr::Func(name, args) = &func.symble {
-            if let Some(existing) = self.map.get(name) {
-                if let Expr::Func(_, exist_args) = &existing.symble {
-                    if exist_args.len() == args.len() {
-                        // 同名同参且表达式体完全相同，视为重复定义
-                        if existing.body == func.body {
-                            return Ok(true);
-                        } else {
-                            return Err(anyhow::Error::msg(
-                                "function with same name and arg count has different expression",
-                            ));
-                        }
-                    }
+            if let Some(existing) = self.find(name, args.len()) {
+                // 参数名和表达式体都完全相同才视为重复定义，f(y,x)=x-y 与 f(x,y)=x-y 不同
+                if existing == func {
+                    return Ok(true);
+                } else {
+                    return Err(CalcError::Redefinition {
+                        name: name.clone(),
+                        arity: args.len(),
+                    });
                 }
             }
             Ok(false)
         } else {
-            Err(anyhow::Error::msg("expect Expr::Func"))
+            Err(CalcError::IllegalFunction)
         }
     }
 }
 
+impl Default for FunctionTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for Function {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}={}", self.symble, self.body)
+        if f.alternate() {
+            write!(f, "{:#}={:#}", self.symble, self.body)
+        } else {
+            write!(f, "{}={}", self.symble, self.body)
+        }
     }
 }
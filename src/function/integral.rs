@@ -1,129 +1,318 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, f64::consts::E, rc::Rc};
 
 use crate::{
+    error::CalcError,
     expr::Expr,
-    function::{Function, FunctionTable},
+    function::{DerivativeMode, Function, FunctionTable, heuristic},
 };
 
+fn add(a: Expr, b: Expr) -> Expr {
+    Expr::Add(Box::new(a), Box::new(b))
+}
+
+fn sub(a: Expr, b: Expr) -> Expr {
+    Expr::Sub(Box::new(a), Box::new(b))
+}
+
+fn mul(a: Expr, b: Expr) -> Expr {
+    Expr::Mul(Box::new(a), Box::new(b))
+}
+
+fn div(a: Expr, b: Expr) -> Expr {
+    Expr::Div(Box::new(a), Box::new(b))
+}
+
+fn pow(a: Expr, b: Expr) -> Expr {
+    Expr::Power(Box::new(a), Box::new(b))
+}
+
+fn ln(a: Expr) -> Expr {
+    Expr::Log(Box::new(Expr::Const(E)), Box::new(a))
+}
+
+fn trifunc(name: &str, a: Expr) -> Expr {
+    Expr::Trifuncs(name.to_string(), Box::new(a))
+}
+
+/// 启发式策略（换元、分部积分）的最大嵌套层数。
+const MAX_DEPTH: usize = 3;
+
+fn no_closed_form(expr: &Expr) -> CalcError {
+    CalcError::NoClosedForm(expr.to_string())
+}
+
 impl Expr {
-    fn find_var(&self, var: String, args: &Vec<Expr>) -> bool {
-        for i in 0..args.len() {
-            if let Expr::Var(name) = args[i].clone()
-                && name == var
-            {
-                return true;
-            }
+    /// 表达式是否含有变量 `dx`（包括出现在函数调用参数中的情形）。
+    pub(crate) fn contains_var(&self, dx: &str) -> bool {
+        match self {
+            Expr::Const(_) => false,
+            Expr::Var(x) => x == dx,
+            Expr::Func(_, args) => args.iter().any(|a| a.contains_var(dx)),
+            Expr::Add(x, y)
+            | Expr::Sub(x, y)
+            | Expr::Mul(x, y)
+            | Expr::Div(x, y)
+            | Expr::Power(x, y)
+            | Expr::Log(x, y)
+            | Expr::Equal(x, y) => x.contains_var(dx) || y.contains_var(dx),
+            Expr::Neg(x) | Expr::Trifuncs(_, x) => x.contains_var(dx),
         }
+    }
 
-        false
+    /// 若表达式是 `dx` 的线性函数 a*dx+b（a ≠ 0），返回斜率 a。
+    fn linear_slope(
+        &self,
+        dx: &String,
+        args: &[Expr],
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Option<Expr> {
+        let slope = self
+            .derivative(dx, args, function_table, DerivativeMode::Inline)
+            .ok()?
+            .simplify();
+
+        if slope.contains_var(dx) || slope == Expr::Const(0.0) {
+            None
+        } else {
+            Some(slope)
+        }
     }
 
-    fn integral(
+    /// 对表达式按变量 `dx` 求不定积分（省略积分常数）。
+    ///
+    /// 覆盖多项式、指数、对数、三角函数族以及自变量为线性函数 a*x+b 的情形；
+    /// 无法处理的情形返回 "no closed form found"，不会给出错误的结果。
+    /// 为了让结果可以直接求值，ln|u| 记为 ln(u)。
+    pub(super) fn integral(
         &self,
         dx: &String,
-        args: &Vec<Expr>,
+        args: &[Expr],
         function_table: Rc<RefCell<FunctionTable>>,
-    ) -> Result<Expr, anyhow::Error> {
-        if let Expr::Const(k) = self {
-            let kp=Box::<Expr>::new(k);
-            let x=Box::<Expr>::new(Expr::Var(dx));
-            Ok(Expr::Mul(kp,x))
-        } else if let Expr::Var(x) = self {
-            if self.find_var(*x, args) {
-                if *dx == *x {
-                    Ok(Expr::Var(*dx))
+    ) -> Result<Expr, CalcError> {
+        self.integral_depth(dx, args, function_table, MAX_DEPTH)
+    }
+
+    /// 先套用基本积分规则，失败时在剩余 `depth` 层内尝试启发式策略。
+    pub(super) fn integral_depth(
+        &self,
+        dx: &String,
+        args: &[Expr],
+        function_table: Rc<RefCell<FunctionTable>>,
+        depth: usize,
+    ) -> Result<Expr, CalcError> {
+        match self.integral_rules(dx, args, function_table.clone(), depth) {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                if depth > 0
+                    && let Some(res) = self.heuristic_integral(dx, args, function_table, depth - 1)
+                {
+                    Ok(res)
                 } else {
-                    Ok(Expr::Const(1.0))
+                    Err(e)
                 }
-            } else {
-                Err(anyhow::Error::msg(format!("unknown var: {}", x)))
             }
-        } else if let Expr::Add(x, y) = self {
-            let l = x.as_ref().integral(dx, args, function_table.clone())?;
-            let r = y.as_ref().integral(dx, args, function_table.clone())?;
-            Ok(Expr::Add(Box::<Expr>::new(l), Box::<Expr>::new(r)))
-        } else if let Expr::Sub(x, y) = self {
-            let l = x.as_ref().integral(dx, args, function_table.clone())?;
-            let r = y.as_ref().integral(dx, args, function_table.clone())?;
-            Ok(Expr::Sub(Box::<Expr>::new(l), Box::<Expr>::new(r)))
-        } else if let Expr::Mul(x, y) = self {
-            let l = x.as_ref().integral(dx, args, function_table.clone())?;
-            let r = y.as_ref().integral(dx, args, function_table.clone())?;
-            Ok(Expr::Mul(Box::<Expr>::new(l), Box::<Expr>::new(r)))
-        } else if let Expr::Div(x, y) = self {
-            let l = x.as_ref().integral(dx, args, function_table.clone())?;
-            let r = y.as_ref().integral(dx, args, function_table.clone())?;
-            Ok(Expr::Div(Box::<Expr>::new(l), Box::<Expr>::new(r)))
-        } else if let Expr::Power(x, y) = self {
-            let base = x.as_ref().integral(dx, args, function_table.clone())?;
-            if let Expr::Var(_) = base {
-                let p = y.as_ref().integral(dx, args, function_table.clone())?;
-                if let Expr::Var(_) = p {
-                    todo!()
-                } else {
-                    let mut k=Expr::Sub(
-                            Box::<Expr>::new(p),
-                            Box::<Expr>::new(Expr::Const(1.0)),
-                        );
-
-                    let fk=Function::new_with_expr(k);
-                    fk.symble = Expr::Func("".to_string(), args.clone());
-                    let t=args.len() as i32;
-                    let tvec=(0..t).collect();
-                    if fk.caculate(&tvec, function_table.clone())?==0.0{
+        }
+    }
 
-                    }
+    fn integral_rules(
+        &self,
+        dx: &String,
+        args: &[Expr],
+        function_table: Rc<RefCell<FunctionTable>>,
+        depth: usize,
+    ) -> Result<Expr, CalcError> {
+        if !self.contains_var(dx) {
+            // ∫c dx = c*x
+            return Ok(mul(self.clone(), Expr::Var(dx.clone())));
+        }
 
-                    let box_p = Box::<Expr>::new(Expr::Power(
-                        Box::<Expr>::new(base),
-                        Box::<Expr>::new(k),
-                    ));
-                    return Ok(Expr::Mul(Box::<Expr>::new(p), box_p));
+        match self {
+            // ∫x dx = x^2/2
+            Expr::Var(_) => Ok(div(pow(self.clone(), Expr::Const(2.0)), Expr::Const(2.0))),
+            Expr::Add(x, y) => Ok(add(
+                x.integral_depth(dx, args, function_table.clone(), depth)?,
+                y.integral_depth(dx, args, function_table.clone(), depth)?,
+            )),
+            Expr::Sub(x, y) => Ok(sub(
+                x.integral_depth(dx, args, function_table.clone(), depth)?,
+                y.integral_depth(dx, args, function_table.clone(), depth)?,
+            )),
+            Expr::Neg(x) => Ok(Expr::Neg(Box::new(x.integral_depth(
+                dx,
+                args,
+                function_table.clone(),
+                depth,
+            )?))),
+            Expr::Mul(x, y) => {
+                // 只有常数因子可以提到积分号外
+                if !x.contains_var(dx) {
+                    Ok(mul(
+                        x.as_ref().clone(),
+                        y.integral_depth(dx, args, function_table.clone(), depth)?,
+                    ))
+                } else if !y.contains_var(dx) {
+                    Ok(mul(
+                        x.integral_depth(dx, args, function_table.clone(), depth)?,
+                        y.as_ref().clone(),
+                    ))
+                } else {
+                    Err(no_closed_form(self))
                 }
-            } else {
-                Ok(Expr::Const(1.0))
             }
-        } else if let Expr::Log(x, y) = self {
-            if let Expr::Var(_) = x.as_ref() {
-                todo!()
-            } else {
-                if let Expr::Var(p) = y.as_ref() {
-                    let inner=y.as_ref().integral(dx, args, function_table.clone())?;
-
-                    if let Expr::Var(_)=inner{
-
-                    }
-                    todo!()
-                }else{
-                    Ok(Expr::Const(1.0))
+            Expr::Div(x, y) => {
+                if !y.contains_var(dx) {
+                    Ok(div(
+                        x.integral_depth(dx, args, function_table.clone(), depth)?,
+                        y.as_ref().clone(),
+                    ))
+                } else if !x.contains_var(dx) {
+                    // c/v = c*v^-1，v 本身是乘方时合并指数
+                    let inv = match y.as_ref() {
+                        Expr::Power(u, k) if !k.contains_var(dx) => {
+                            pow(u.as_ref().clone(), Expr::Neg(k.clone()).simplify())
+                        }
+                        v => pow(v.clone(), Expr::Const(-1.0)),
+                    };
+                    Ok(mul(
+                        x.as_ref().clone(),
+                        inv.integral_depth(dx, args, function_table.clone(), depth)
+                            .map_err(|_| no_closed_form(self))?,
+                    ))
+                } else {
+                    Err(no_closed_form(self))
                 }
             }
-        } else if let Expr::Trifuncs(ref name, pvar) = self {
-            let mut var_func = Function::new_with_expr(*pvar);
-            var_func.symble = Expr::Func("".to_string(), orig_args.clone());
-
-            Ok(self.caculate_trifuncs(name, var_func.caculate(arg, function_table)?)?)
-        } else if let Expr::Func(name, args) = self {
-            if let Some(func) = function_table.borrow().find(&name, args.len()) {
-                let mut argc = Vec::<f64>::new();
-                for i in args.clone() {
-                    let tfunc = Function::new_with_expr(i);
-                    argc.push(tfunc.caculate(arg, function_table.clone())?)
+            Expr::Power(u, k) if !k.contains_var(dx) => {
+                // ∫u^k dx = u^(k+1)/((k+1)*a)，k = -1 时为 ln(u)/a
+                let Some(a) = u.linear_slope(dx, args, function_table.clone()) else {
+                    return Err(no_closed_form(self));
+                };
+                let k = k.as_ref().simplify();
+                if k == Expr::Const(-1.0) {
+                    Ok(div(ln(u.as_ref().clone()), a))
+                } else {
+                    let k1 = add(k, Expr::Const(1.0)).simplify();
+                    Ok(div(pow(u.as_ref().clone(), k1.clone()), mul(k1, a)))
                 }
+            }
+            Expr::Power(c, u) if !c.contains_var(dx) => {
+                // ∫c^u dx = c^u/(ln(c)*a)
+                let Some(a) = u.linear_slope(dx, args, function_table.clone()) else {
+                    return Err(no_closed_form(self));
+                };
+                let denom = match c.as_ref() {
+                    Expr::Const(v) if *v == E => a,
+                    _ => mul(ln(c.as_ref().clone()), a),
+                };
+                Ok(div(self.clone(), denom))
+            }
+            Expr::Log(b, u) if !b.contains_var(dx) => {
+                // ∫log_b(u) dx = (u*ln(u) - u)/(a*ln(b))
+                let Some(a) = u.linear_slope(dx, args, function_table.clone()) else {
+                    return Err(no_closed_form(self));
+                };
+                let u = u.as_ref().clone();
+                let anti = sub(mul(u.clone(), ln(u.clone())), u);
+                let denom = match b.as_ref() {
+                    Expr::Const(v) if *v == E => a,
+                    _ => mul(a, ln(b.as_ref().clone())),
+                };
+                Ok(div(anti, denom))
+            }
+            Expr::Trifuncs(name, u) => {
+                let Some(a) = u.linear_slope(dx, args, function_table.clone()) else {
+                    return Err(no_closed_form(self));
+                };
+                let u = u.as_ref().clone();
+                let sqrt_1_u2 = pow(
+                    sub(Expr::Const(1.0), pow(u.clone(), Expr::Const(2.0))),
+                    Expr::Const(0.5),
+                );
+                let anti = match name.as_str() {
+                    // ∫sin u = -cos u
+                    "sin" => Expr::Neg(Box::new(trifunc("cos", u))),
+                    // ∫cos u = sin u
+                    "cos" => trifunc("sin", u),
+                    // ∫tan u = -ln(cos u)
+                    "tan" => Expr::Neg(Box::new(ln(trifunc("cos", u)))),
+                    // ∫cot u = ln(sin u)
+                    "cot" => ln(trifunc("sin", u)),
+                    // ∫sec u = ln(sec u + tan u)
+                    "sec" => ln(add(trifunc("sec", u.clone()), trifunc("tan", u))),
+                    // ∫csc u = -ln(csc u + cot u)
+                    "csc" => Expr::Neg(Box::new(ln(add(
+                        trifunc("csc", u.clone()),
+                        trifunc("cot", u),
+                    )))),
+                    // ∫arcsin u = u*arcsin u + sqrt(1-u^2)
+                    "arcsin" => add(mul(u.clone(), trifunc("arcsin", u)), sqrt_1_u2),
+                    // ∫arccos u = u*arccos u - sqrt(1-u^2)
+                    "arccos" => sub(mul(u.clone(), trifunc("arccos", u)), sqrt_1_u2),
+                    // ∫arctan u = u*arctan u - ln(1+u^2)/2
+                    "arctan" => sub(
+                        mul(u.clone(), trifunc("arctan", u.clone())),
+                        div(
+                            ln(add(Expr::Const(1.0), pow(u, Expr::Const(2.0)))),
+                            Expr::Const(2.0),
+                        ),
+                    ),
+                    _ => return Err(no_closed_form(self)),
+                };
+                Ok(div(anti, a))
+            }
+            Expr::Func(name, call_args) => {
+                // 用户自定义函数：代入函数体后再积分
+                let callee = match function_table.borrow().find(name, call_args.len()) {
+                    Some(func) => func.clone(),
+                    None => {
+                        return Err(CalcError::UnknownFunction {
+                            name: name.to_string(),
+                            arity: call_args.len(),
+                            span: None,
+                        });
+                    }
+                };
+                let Expr::Func(_, params) = &callee.symble else {
+                    return Err(CalcError::IllegalFunction);
+                };
+                callee.body.substitute(params, call_args).integral_depth(
+                    dx,
+                    args,
+                    function_table.clone(),
+                    depth,
+                )
+            }
+            _ => Err(no_closed_form(self)),
+        }
+    }
+}
 
-                #[cfg(debug_assertions)]
-                {
-                    println!("Expr::Func({},{})", name, args.len());
-                }
+impl Function {
+    /// 对变量 `dx` 求不定积分，结果按函数名和积分变量命名为 `int_f_x`，积分常数取 0。
+    pub fn integral(
+        &self,
+        dx: &String,
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<Function, CalcError> {
+        if let Expr::Func(name, args) = self.symble.clone() {
+            if !args.contains(&Expr::Var(dx.clone())) {
+                return Err(CalcError::UnknownVariable(dx.to_string()));
+            }
 
-                match func.caculate(&argc, function_table.clone()) {
-                    Ok(v) => return Ok(v),
-                    Err(e) => return Err(anyhow::Error::msg(format!("unknown function. {}", e))),
-                }
+            let body = self
+                .body
+                .integral(dx, &args, function_table.clone())?
+                .simplify();
+            // 对结果求导并与被积函数做数值比较，防止给出错误的原函数
+            if !heuristic::verify(&self.body, &body, dx, &args, function_table.clone()) {
+                return Err(CalcError::IntegrationCheckFailed(self.body.to_string()));
             }
-            Err(anyhow::Error::msg("unknown caculation"))
+            Ok(Function {
+                symble: Expr::Func(format!("int_{}_{}", name, dx), args),
+                body,
+            })
         } else {
-            Err(anyhow::Error::msg("unknown caculation"))
+            Err(CalcError::IllegalFunction)
         }
     }
-}
\ No newline at end of file
+}
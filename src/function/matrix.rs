@@ -0,0 +1,204 @@
+use std::{cell::RefCell, fmt::Display, rc::Rc};
+
+use crate::{
+    error::CalcError,
+    expr::Expr,
+    function::{Function, FunctionTable},
+};
+
+/// 由函数组成的向量，例如梯度 ∇f = [f_x, f_y]。
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionVector {
+    pub(crate) name: String,
+    pub(crate) params: Vec<Expr>,
+    pub(crate) items: Vec<Function>,
+}
+
+/// 由函数组成的矩阵，例如 Jacobian 和 Hessian。
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionMatrix {
+    pub(crate) name: String,
+    pub(crate) params: Vec<Expr>,
+    pub(crate) rows: Vec<Vec<Function>>,
+}
+
+impl FunctionVector {
+    pub fn items(&self) -> &[Function] {
+        &self.items
+    }
+
+    /// 在点 `args` 处求值。
+    pub fn caculate(
+        &self,
+        args: &[f64],
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<Vec<f64>, CalcError> {
+        self.items
+            .iter()
+            .map(|f| f.caculate(args, function_table.clone()))
+            .collect()
+    }
+}
+
+impl FunctionMatrix {
+    pub fn rows(&self) -> &[Vec<Function>] {
+        &self.rows
+    }
+
+    /// 在点 `args` 处求值，结果按行排列。
+    pub fn caculate(
+        &self,
+        args: &[f64],
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<Vec<Vec<f64>>, CalcError> {
+        self.rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|f| f.caculate(args, function_table.clone()))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl Function {
+    pub(super) fn params(&self) -> Result<Vec<Expr>, CalcError> {
+        match &self.symble {
+            Expr::Func(_, params) => Ok(params.clone()),
+            _ => Err(CalcError::IllegalFunction),
+        }
+    }
+
+    fn param_names(&self) -> Result<Vec<String>, CalcError> {
+        self.params()?
+            .iter()
+            .map(|p| match p {
+                Expr::Var(name) => Ok(name.clone()),
+                _ => Err(CalcError::IllegalFunction),
+            })
+            .collect()
+    }
+
+    /// 梯度 ∇f = [∂f/∂x1, ..., ∂f/∂xn]。
+    pub fn gradient(
+        &self,
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<FunctionVector, CalcError> {
+        let mut items = Vec::new();
+        for var in self.param_names()? {
+            items.push(self.partial_derivative(&[var], function_table.clone())?);
+        }
+
+        Ok(FunctionVector {
+            name: format!("grad {}", self.name()),
+            params: self.params()?,
+            items,
+        })
+    }
+
+    /// Hessian 矩阵 H[i][j] = ∂²f/∂xi∂xj。
+    ///
+    /// 只计算上三角部分，下三角直接复用对称位置的结果。
+    pub fn hessian(
+        &self,
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<FunctionMatrix, CalcError> {
+        let vars = self.param_names()?;
+        let n = vars.len();
+        let mut rows: Vec<Vec<Function>> = vec![Vec::with_capacity(n); n];
+
+        for i in 0..n {
+            for j in 0..n {
+                let entry = if j < i {
+                    rows[j][i].clone()
+                } else {
+                    let order = [vars[i].clone(), vars[j].clone()];
+                    self.partial_derivative(&order, function_table.clone())?
+                };
+                rows[i].push(entry);
+            }
+        }
+
+        Ok(FunctionMatrix {
+            name: format!("hessian {}", self.name()),
+            params: self.params()?,
+            rows,
+        })
+    }
+
+    /// 一组函数的 Jacobian 矩阵 J[i][j] = ∂fi/∂xj，要求所有函数的参数列表相同。
+    pub fn jacobian(
+        funcs: &[Function],
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<FunctionMatrix, CalcError> {
+        let Some(first) = funcs.first() else {
+            return Err(CalcError::InvalidArgument(
+                "jacobian of an empty function list".to_string(),
+            ));
+        };
+
+        let params = first.params()?;
+        let mut rows = Vec::new();
+        for func in funcs {
+            if func.params()? != params {
+                return Err(CalcError::InvalidArgument(format!(
+                    "jacobian needs the same parameters: {} and {}",
+                    first.symble, func.symble
+                )));
+            }
+            rows.push(func.gradient(function_table.clone())?.items);
+        }
+
+        let names: Vec<&str> = funcs.iter().map(|f| f.name()).collect();
+        Ok(FunctionMatrix {
+            name: format!("jacobian ({})", names.join(",")),
+            params,
+            rows,
+        })
+    }
+}
+
+fn fmt_params(f: &mut std::fmt::Formatter<'_>, params: &[Expr]) -> std::fmt::Result {
+    write!(f, "(")?;
+    for (i, p) in params.iter().enumerate() {
+        if i != 0 {
+            write!(f, ",")?;
+        }
+        write!(f, "{}", p)?;
+    }
+    write!(f, ")")
+}
+
+fn fmt_row(f: &mut std::fmt::Formatter<'_>, row: &[Function]) -> std::fmt::Result {
+    write!(f, "[")?;
+    for (i, item) in row.iter().enumerate() {
+        if i != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item.body)?;
+    }
+    write!(f, "]")
+}
+
+impl Display for FunctionVector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        fmt_params(f, &self.params)?;
+        write!(f, "=")?;
+        fmt_row(f, &self.items)
+    }
+}
+
+impl Display for FunctionMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        fmt_params(f, &self.params)?;
+        write!(f, "=")?;
+        for row in &self.rows {
+            writeln!(f)?;
+            fmt_row(f, row)?;
+        }
+        Ok(())
+    }
+}
@@ -1,21 +1,61 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::expr::{Expr, Token};
+use crate::expr::Expr;
+pub mod batch;
 pub mod caculate;
+pub mod compile;
+pub mod cubature;
 pub mod derivative;
+pub mod heuristic;
 pub mod implement;
-pub mod parse;
+pub mod integral;
+pub mod matrix;
+pub mod quadrature;
+pub mod shared;
+pub mod workspace;
 
-#[derive(Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Function {
     pub(crate) symble: Expr,
-    body: Expr,
+    pub(crate) body: Expr,
+}
+
+/// 函数在 `FunctionTable` 中的键：(函数名, 参数个数)。
+pub type FunctionKey = (String, usize);
+
+/// 向 `FunctionTable` 中定义函数时，遇到同名同参函数的处理方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefineMode {
+    /// 已有同名同参但表达式不同的函数时报错
+    Strict,
+    /// 用新定义替换旧定义
+    Replace,
+}
+
+/// 求导时对用户自定义函数调用应用链式法则的方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerivativeMode {
+    /// 把被调函数的导数代入调用处展开
+    Inline,
+    /// 保留 `f_x(g(x))` 形式的符号引用，并把 `f_x` 写入 `FunctionTable`
+    Symbolic,
+}
 
-    tokens: Vec<Token>,
-    i: usize,
+/// 求值时对 NaN 和 ±∞ 的处理方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalMode {
+    /// 按 IEEE 754 运算，NaN 和 ±∞ 沿表达式传播
+    Lenient,
+    /// 第一个结果不是有限值的子表达式处报错
+    Checked,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct FunctionTable {
-    map: HashMap<String, Function>,
+    /// 以 (函数名, 参数个数) 为键，允许按参数个数重载
+    map: HashMap<FunctionKey, Function>,
+    /// 每个函数直接依赖的函数：函数体中调用的函数，以及它由之求导得到的函数
+    deps: HashMap<FunctionKey, HashSet<FunctionKey>>,
+    /// 缓存的高阶偏导数：(原函数, 求导变量序列) -> 偏导函数
+    partials: HashMap<(FunctionKey, Vec<String>), Function>,
 }
@@ -0,0 +1,296 @@
+use std::{cell::RefCell, cmp::Ordering, collections::BinaryHeap, fmt::Display, rc::Rc};
+
+use crate::{
+    error::CalcError,
+    expr::Expr,
+    function::{Function, FunctionTable},
+};
+
+/// Kronrod 15 点节点（非负部分，降序），奇数下标同时是 Gauss 7 点节点。
+const XGK: [f64; 8] = [
+    0.9914553711208126,
+    0.9491079123427585,
+    0.8648644233597691,
+    0.7415311855993945,
+    0.5860872354676911,
+    0.4058451513773972,
+    0.20778495500789848,
+    0.0,
+];
+
+/// Kronrod 15 点权重，与 XGK 对应。
+const WGK: [f64; 8] = [
+    0.022935322010529224,
+    0.06309209262997856,
+    0.10479001032225019,
+    0.14065325971552592,
+    0.1690047266392679,
+    0.19035057806478542,
+    0.20443294007529889,
+    0.20948214108472782,
+];
+
+/// Gauss 7 点权重，对应 XGK[1], XGK[3], XGK[5], XGK[7]。
+const WG: [f64; 4] = [
+    0.1294849661688697,
+    0.27970539148927664,
+    0.3818300505051189,
+    0.4179591836734694,
+];
+
+/// 数值积分的容差设置，满足 error <= max(abs_tol, rel_tol * |value|) 即停止。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuadratureOptions {
+    pub abs_tol: f64,
+    pub rel_tol: f64,
+    /// 被积函数求值次数上限
+    pub max_evaluations: usize,
+}
+
+/// 数值积分结果：积分值、估计误差和被积函数求值次数。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuadratureResult {
+    pub value: f64,
+    pub error: f64,
+    pub evaluations: usize,
+}
+
+impl Default for QuadratureOptions {
+    fn default() -> Self {
+        QuadratureOptions {
+            abs_tol: 1e-10,
+            rel_tol: 1e-10,
+            max_evaluations: 200_000,
+        }
+    }
+}
+
+impl Display for QuadratureResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ± {:e} ({} evaluations)",
+            self.value, self.error, self.evaluations
+        )
+    }
+}
+
+/// 自适应细分中的一个区间，按估计误差排序。
+struct Segment {
+    a: f64,
+    b: f64,
+    value: f64,
+    error: f64,
+}
+
+impl PartialEq for Segment {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Segment {}
+
+impl PartialOrd for Segment {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Segment {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.error.total_cmp(&other.error)
+    }
+}
+
+/// 在 [a, b] 上做一次 G7K15，返回 Kronrod 结果和 |K - G| 作为误差估计。
+fn gauss_kronrod(
+    g: &mut dyn FnMut(f64) -> Result<f64, CalcError>,
+    a: f64,
+    b: f64,
+) -> Result<Segment, CalcError> {
+    let center = 0.5 * (a + b);
+    let half = 0.5 * (b - a);
+
+    let fc = g(center)?;
+    let mut kronrod = fc * WGK[7];
+    let mut gauss = fc * WG[3];
+
+    for (i, (x, w)) in XGK[..7].iter().zip(WGK).enumerate() {
+        let fsum = g(center - half * x)? + g(center + half * x)?;
+        kronrod += w * fsum;
+        if i % 2 == 1 {
+            gauss += WG[i / 2] * fsum;
+        }
+    }
+
+    Ok(Segment {
+        a,
+        b,
+        value: kronrod * half,
+        error: ((kronrod - gauss) * half).abs(),
+    })
+}
+
+/// 在有限区间 [lo, hi] 上对 `g` 做全局自适应 G7K15 积分。
+fn adaptive(
+    g: &mut dyn FnMut(f64) -> Result<f64, CalcError>,
+    lo: f64,
+    hi: f64,
+    options: &QuadratureOptions,
+) -> Result<QuadratureResult, CalcError> {
+    let mut heap = BinaryHeap::new();
+    let first = gauss_kronrod(g, lo, hi)?;
+    let mut evaluations = 15;
+    let (mut value, mut error) = (first.value, first.error);
+    heap.push(first);
+
+    loop {
+        if error <= options.abs_tol.max(options.rel_tol * value.abs()) {
+            // 累加的和有舍入漂移，结束前重新求和
+            value = heap.iter().map(|s| s.value).sum();
+            error = heap.iter().map(|s| s.error).sum();
+            if error <= options.abs_tol.max(options.rel_tol * value.abs()) {
+                return Ok(QuadratureResult {
+                    value,
+                    error,
+                    evaluations,
+                });
+            }
+        }
+
+        if evaluations + 30 > options.max_evaluations {
+            return Err(CalcError::NotConverged {
+                value,
+                error,
+                evaluations,
+            });
+        }
+
+        let Some(worst) = heap.pop() else {
+            unreachable!("heap always holds at least one segment");
+        };
+        let mid = 0.5 * (worst.a + worst.b);
+        if mid <= worst.a || mid >= worst.b {
+            // 区间已无法再分，同样视为未收敛
+            return Err(CalcError::NotConverged {
+                value,
+                error,
+                evaluations,
+            });
+        }
+
+        let left = gauss_kronrod(g, worst.a, mid)?;
+        let right = gauss_kronrod(g, mid, worst.b)?;
+        evaluations += 30;
+
+        value += left.value + right.value - worst.value;
+        error += left.error + right.error - worst.error;
+        heap.push(left);
+        heap.push(right);
+    }
+}
+
+/// 计算定积分 ∫_a^b f(x) dx，`a`、`b` 可以是 ±∞。
+///
+/// 积分前先做变量代换：有限区间用 x = a + (b-a)(3t²-2t³)，
+/// 使端点处 1/√x、ln(x) 一类的奇异性变得可积且平滑；
+/// 无穷区间用 x = a + t/(1-t) 等映射到有限区间。
+/// Gauss–Kronrod 节点不含区间端点，因此不会在奇点处求值。
+pub fn integrate(
+    f: &mut dyn FnMut(f64) -> Result<f64, CalcError>,
+    a: f64,
+    b: f64,
+    options: &QuadratureOptions,
+) -> Result<QuadratureResult, CalcError> {
+    if a.is_nan() || b.is_nan() {
+        return Err(CalcError::InvalidArgument(
+            "integration bound is NaN".to_string(),
+        ));
+    }
+    if a == b {
+        return Ok(QuadratureResult {
+            value: 0.0,
+            error: 0.0,
+            evaluations: 0,
+        });
+    }
+    if a > b {
+        let res = integrate(f, b, a, options)?;
+        return Ok(QuadratureResult {
+            value: -res.value,
+            ..res
+        });
+    }
+
+    // 把 f 与代换的导数相乘，得到 t 上的被积函数
+    let mut checked = |x: f64, w: f64| -> Result<f64, CalcError> {
+        let y = f(x)?;
+        if !y.is_finite() {
+            return Err(CalcError::Domain(format!(
+                "integrand is not finite at x = {}: {}",
+                x, y
+            )));
+        }
+        Ok(y * w)
+    };
+
+    match (a.is_finite(), b.is_finite()) {
+        (true, true) => {
+            let len = b - a;
+            let mut g =
+                |t: f64| checked(a + len * t * t * (3.0 - 2.0 * t), 6.0 * len * t * (1.0 - t));
+            adaptive(&mut g, 0.0, 1.0, options)
+        }
+        (true, false) => {
+            let mut g = |t: f64| checked(a + t / (1.0 - t), 1.0 / ((1.0 - t) * (1.0 - t)));
+            adaptive(&mut g, 0.0, 1.0, options)
+        }
+        (false, true) => {
+            let mut g = |t: f64| checked(b - (1.0 - t) / t, 1.0 / (t * t));
+            adaptive(&mut g, 0.0, 1.0, options)
+        }
+        (false, false) => {
+            let mut g = |t: f64| {
+                let d = 1.0 - t * t;
+                checked(t / d, (1.0 + t * t) / (d * d))
+            };
+            adaptive(&mut g, -1.0, 1.0, options)
+        }
+    }
+}
+
+impl Function {
+    /// 数值计算定积分 ∫_a^b f dx，其余参数按顺序取 `fixed` 中的值。
+    pub fn definite_integral(
+        &self,
+        dx: &String,
+        a: f64,
+        b: f64,
+        fixed: &[f64],
+        options: &QuadratureOptions,
+        function_table: Rc<RefCell<FunctionTable>>,
+    ) -> Result<QuadratureResult, CalcError> {
+        let params = self.params()?;
+        let Some(index) = params.iter().position(|p| *p == Expr::Var(dx.clone())) else {
+            return Err(CalcError::UnknownVariable(dx.to_string()));
+        };
+        if fixed.len() + 1 != params.len() {
+            return Err(CalcError::ArityMismatch {
+                expected: params.len() - 1,
+                found: fixed.len(),
+            });
+        }
+
+        let mut point = fixed.to_vec();
+        point.insert(index, 0.0);
+
+        let compiled = self.compile(function_table)?;
+        let mut stack = Vec::new();
+        let mut f = |x: f64| {
+            point[index] = x;
+            compiled.caculate_with(&point, &mut stack)
+        };
+        integrate(&mut f, a, b, options)
+    }
+}
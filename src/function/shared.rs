@@ -0,0 +1,76 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{
+    error::CalcError,
+    expr::SpannedToken,
+    function::{DefineMode, Function, FunctionTable, compile::CompiledFunction},
+};
+
+/// 可在线程间共享的 `FunctionTable`，读多写少，克隆得到的是同一张表的另一个句柄。
+#[derive(Debug, Clone, Default)]
+pub struct SharedFunctionTable {
+    inner: Arc<RwLock<FunctionTable>>,
+}
+
+impl SharedFunctionTable {
+    pub fn new() -> SharedFunctionTable {
+        Self::default()
+    }
+
+    /// 读锁，可同时被多个线程持有。持锁线程 panic 后表仍然可用。
+    pub fn read(&self) -> RwLockReadGuard<'_, FunctionTable> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 写锁，定义和删除函数时使用。
+    pub fn write(&self) -> RwLockWriteGuard<'_, FunctionTable> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 当前内容的不可变快照，之后的修改不会影响它。
+    pub fn snapshot(&self) -> FunctionTable {
+        self.read().clone()
+    }
+
+    /// 解析并按 `mode` 定义函数，整个过程持有写锁。
+    pub fn define(&self, tokens: &[SpannedToken], mode: DefineMode) -> Result<Function, CalcError> {
+        let mut table = self.write();
+        let func = Function::parse_definition(tokens, &table)?;
+        table.define(func.clone(), mode)?;
+        Ok(func)
+    }
+
+    /// 在点 `args` 处对 `name` 求值，参数个数决定调用哪个重载。
+    pub fn caculate(&self, name: &str, args: &[f64]) -> Result<f64, CalcError> {
+        let table = self.read();
+        let func = table
+            .find(name, args.len())
+            .ok_or_else(|| CalcError::UnknownFunction {
+                name: name.to_string(),
+                arity: args.len(),
+                span: None,
+            })?;
+        func.caculate_in(args, &table)
+    }
+
+    /// 编译 `name/argc`；编译结果不再引用表，可以直接交给工作线程反复求值。
+    pub fn compile(&self, name: &str, argc: usize) -> Result<CompiledFunction, CalcError> {
+        let table = self.read();
+        let func = table
+            .find(name, argc)
+            .ok_or_else(|| CalcError::UnknownFunction {
+                name: name.to_string(),
+                arity: argc,
+                span: None,
+            })?;
+        func.compile_in(&table)
+    }
+}
+
+impl From<FunctionTable> for SharedFunctionTable {
+    fn from(table: FunctionTable) -> Self {
+        SharedFunctionTable {
+            inner: Arc::new(RwLock::new(table)),
+        }
+    }
+}
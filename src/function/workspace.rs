@@ -0,0 +1,115 @@
+use std::{collections::HashSet, path::Path};
+
+use crate::{
+    error::CalcError,
+    function::{DefineMode, Function, FunctionKey, FunctionTable},
+    tokenlizer::Tokenlizer,
+};
+
+/// 解析工作区中的一行 `name(args)=body`。
+fn parse_line(line: &str, function_table: &FunctionTable) -> Result<Function, CalcError> {
+    let tokens = Tokenlizer::new(line).tokenlize()?;
+    Function::parse_definition(&tokens, function_table)
+}
+
+impl FunctionTable {
+    /// 按依赖顺序排列的所有函数：被调用或被求导的函数排在依赖它的函数之前，其余按键排序。
+    pub fn dependency_order(&self) -> Vec<&Function> {
+        let mut keys: Vec<&FunctionKey> = self.map.keys().collect();
+        keys.sort();
+
+        let mut visited = HashSet::new();
+        let mut res = Vec::with_capacity(keys.len());
+        for key in keys {
+            self.visit(key, &mut visited, &mut res);
+        }
+        res
+    }
+
+    /// 深度优先遍历，先输出 `key` 依赖的函数再输出它自己。
+    fn visit<'a>(
+        &'a self,
+        key: &FunctionKey,
+        visited: &mut HashSet<FunctionKey>,
+        res: &mut Vec<&'a Function>,
+    ) {
+        if !visited.insert(key.clone()) {
+            return;
+        }
+        if let Some(deps) = self.deps.get(key) {
+            let mut deps: Vec<&FunctionKey> = deps.iter().collect();
+            deps.sort();
+            for dep in deps {
+                self.visit(dep, visited, res);
+            }
+        }
+        if let Some(func) = self.map.get(key) {
+            res.push(func);
+        }
+    }
+
+    /// 把所有函数按依赖顺序写成文本，每行一个 `name(args)=body`。
+    ///
+    /// 每一行写出前都重新解析检查，读回后与原函数不完全相同时报错，例如含有 NaN 常数。
+    /// 只保存函数本身，导函数与原函数之间的依赖和缓存的偏导数不会保存。
+    pub fn to_workspace(&self) -> Result<String, CalcError> {
+        let mut check = FunctionTable::new();
+        let mut text = String::new();
+
+        for func in self.dependency_order() {
+            let line = format!("{:#}", func);
+            if parse_line(&line, &check).ok().as_ref() != Some(func) {
+                return Err(CalcError::InvalidArgument(format!(
+                    "{} cannot be saved exactly",
+                    line
+                )));
+            }
+            check.insert(func.clone());
+
+            text.push_str(&line);
+            text.push('\n');
+        }
+
+        Ok(text)
+    }
+
+    /// 逐行定义工作区文本中的函数，返回定义的函数个数；空行和 `#` 开头的行被忽略。
+    ///
+    /// 与已有函数冲突或任何一行出错时返回带行号的错误，此时表保持不变。
+    pub fn load_workspace(&mut self, text: &str) -> Result<usize, CalcError> {
+        let mut table = self.clone();
+        let mut count = 0;
+
+        for (i, line) in text.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            let at = |error| CalcError::Workspace {
+                line: i + 1,
+                error: Box::new(error),
+            };
+            let func = parse_line(line, &table).map_err(at)?;
+            table.define(func, DefineMode::Strict).map_err(at)?;
+            count += 1;
+        }
+
+        *self = table;
+        Ok(count)
+    }
+
+    /// 把工作区写入文件 `path`。
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CalcError> {
+        let text = self.to_workspace()?;
+        std::fs::write(path.as_ref(), text)
+            .map_err(|e| CalcError::Io(format!("{}: {}", path.as_ref().display(), e)))
+    }
+
+    /// 从文件 `path` 读入工作区，返回定义的函数个数。
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<usize, CalcError> {
+        let text = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| CalcError::Io(format!("{}: {}", path.as_ref().display(), e)))?;
+        self.load_workspace(&text)
+    }
+}
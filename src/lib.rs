@@ -1,17 +1,42 @@
+#[cfg(test)]
 use std::cell::RefCell;
-use std::io::stdin;
+#[cfg(test)]
 use std::rc::Rc;
 
+#[cfg(test)]
 use crate::{
+    error::{CalcError, render_error},
     expr::Expr,
-    function::{Function, FunctionTable},
+    function::{
+        DefineMode, DerivativeMode, EvalMode, Function, FunctionTable,
+        batch::BatchMode,
+        cubature::{CubatureMethod, Limits},
+        quadrature::QuadratureOptions,
+    },
     tokenlizer::Tokenlizer,
 };
 
+pub mod error;
 pub mod expr;
 pub mod function;
+pub mod parser;
+pub mod pretty;
+pub mod repl;
+#[cfg(feature = "serde")]
+pub mod serialize;
 pub mod tokenlizer;
 
+/// 在 `function_table` 中按 `mode` 定义 `text` 给出的函数。
+#[cfg(test)]
+fn define(
+    function_table: &Rc<RefCell<FunctionTable>>,
+    text: &str,
+    mode: DefineMode,
+) -> Result<Function, CalcError> {
+    let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+    Function::new_with_mode(&tokens, function_table.clone(), mode)
+}
+
 #[test]
 fn test() {
     let function_table = Rc::new(RefCell::new(FunctionTable::new()));
@@ -29,8 +54,8 @@ fn test() {
 
     #[cfg(debug_assertions)]
     {
-        for i in 0..tokens.len() {
-            println!("{}", tokens[i])
+        for token in &tokens {
+            println!("{}", token)
         }
     }
 
@@ -43,21 +68,20 @@ fn test() {
     };
 
     let input = "f(1)".to_string();
-    let mut vec: Vec<&str> = input.trim().split(|c| c == '(' || c == ')').collect();
+    let mut vec: Vec<&str> = input.trim().split(['(', ')']).collect();
     let name = vec[0];
 
-    vec = vec[1].split(|c| c == ',' || c == ' ').collect();
+    vec = vec[1].split([',', ' ']).collect();
 
     let binding = function_table.borrow();
     let func = binding.find(name, vec.len());
-    if func == None {
+    if func.is_none() {
         println!("no such function: {}", name);
-        return;
     } else {
         if let Some(x) = func {
             let mut args = Vec::<f64>::new();
-            for i in 0..vec.len() {
-                args.push(vec[i].parse::<f64>().unwrap());
+            for arg in &vec {
+                args.push(arg.parse::<f64>().unwrap());
             }
 
             let ans = match x.caculate(&args, function_table.clone()) {
@@ -77,102 +101,1087 @@ fn test() {
     }
 }
 
-pub fn put(function_table: Rc<RefCell<FunctionTable>>) {
-    let mut input = String::new();
-    stdin().read_line(&mut input).unwrap();
-    let text = input.trim().to_string();
+#[test]
+fn test_number_literal() {
+    let mut tokenlizer = Tokenlizer::new("12 0.5 .5 2.5e-3 1E2 0x1f 0b101");
+    let tokens = tokenlizer.tokenlize().unwrap();
+    let values: Vec<f64> = tokens.iter().map(|t| t.token.as_const().unwrap()).collect();
+    assert_eq!(values, vec![12.0, 0.5, 0.5, 2.5e-3, 100.0, 31.0, 5.0]);
 
-    let mut tokenlizer = Tokenlizer::new(&text);
+    for text in ["1.2.3", "1e", "2e+", "0x", "0b12"] {
+        let err = Tokenlizer::new(text).tokenlize().unwrap_err().to_string();
+        assert!(err.contains("malformed number literal"), "{}: {}", text, err);
+    }
+}
 
-    let tokens = match tokenlizer.tokenlize() {
-        Ok(res) => res,
-        Err(e) => {
-            println!("Error: {}", e);
-            return;
-        }
-    };
+#[test]
+fn test_parse_error_position() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    let text = "f(x)=(x+1*2";
+    let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+    let err = Function::new(&tokens, function_table.clone()).unwrap_err();
+    assert!(matches!(err, CalcError::Parse { span, .. } if span.start == 11));
+    assert_eq!(
+        render_error(&err, text),
+        "error: expect Operator: ) but find end of input\n  | f(x)=(x+1*2\n  |            ^"
+    );
 
-    #[cfg(debug_assertions)]
+    let err = Tokenlizer::new("f(x)=x#2").tokenlize().unwrap_err();
+    assert!(matches!(err, CalcError::Lex { span, .. } if span.start == 6));
+}
+
+#[test]
+fn test_unary_operator() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    for (text, x, expected, shown) in [
+        ("f(x)=-x^2", 3.0, -9.0, "f(x)=-x^2"),
+        ("g(x)=2*-x", 3.0, -6.0, "g(x)=2*-x"),
+        ("h(x)=+x - -x", 3.0, 6.0, "h(x)=x--x"),
+        ("k(x)=(-x)^2", 3.0, 9.0, "k(x)=(-x)^2"),
+    ] {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        let func = Function::new(&tokens, function_table.clone()).unwrap();
+        assert_eq!(func.caculate(&[x], function_table.clone()).unwrap(), expected);
+        assert_eq!(func.to_string(), shown);
+    }
+
+    // ',' 之后的 '-' 是负号而不是减号
+    let var = |name: &str| Box::new(Expr::Var(name.to_string()));
+    assert_eq!(
+        Expr::parse("f(x,-y)").unwrap(),
+        Expr::Func("f".to_string(), vec![*var("x"), Expr::Neg(var("y"))])
+    );
+    assert_eq!(
+        Expr::parse("log(2,-x)").unwrap(),
+        Expr::Log(Box::new(Expr::Const(2.0)), Box::new(Expr::Neg(var("x"))))
+    );
+    assert_eq!(
+        Expr::parse("f(x, -1)").unwrap(),
+        Expr::Func(
+            "f".to_string(),
+            vec![*var("x"), Expr::Neg(Box::new(Expr::Const(1.0)))]
+        )
+    );
+    assert!(Expr::parse("f(x y)").is_err());
+    for text in ["p(x,y)=x*y", "q(x)=p(x,-x)"] {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        Function::new(&tokens, function_table.clone()).unwrap();
+    }
+    let q = function_table.borrow().find("q", 1).cloned().unwrap();
+    assert_eq!(q.caculate(&[3.0], function_table.clone()).unwrap(), -9.0);
+
+    let binding = function_table.borrow();
+    let f = binding.find("f", 1).unwrap();
+    let df = f.derivative(&"x".to_string(), function_table.clone()).unwrap();
+    assert_eq!(df.caculate(&[3.0], function_table.clone()).unwrap(), -6.0);
+}
+
+#[test]
+fn test_power_associativity() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    for (text, x, expected, shown) in [
+        ("f(x)=x^3^2", 2.0, 512.0, "f(x)=x^9"),
+        ("n(x)=2^x^x", 2.0, 16.0, "n(x)=2^x^x"),
+        ("g(x)=(x^3)^2", 2.0, 64.0, "g(x)=(x^3)^2"),
+        ("h(x)=x^-1", 2.0, 0.5, "h(x)=x^-1"),
+        ("k(x)=2^-x^2", 1.0, 0.5, "k(x)=2^-x^2"),
+        ("m(x)=(-x)^x^(x-1)", 2.0, 4.0, "m(x)=(-x)^x^(x-1)"),
+    ] {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        let func = Function::new(&tokens, function_table.clone()).unwrap();
+        assert_eq!(func.caculate(&[x], function_table.clone()).unwrap(), expected);
+        assert_eq!(func.to_string(), shown);
+
+        // 输出的文本重新解析后应得到同一棵表达式树
+        let reparsed = Tokenlizer::new(&format!("r{}", shown))
+            .tokenlize()
+            .unwrap();
+        let again = Function::new(&reparsed, function_table.clone()).unwrap();
+        assert_eq!(again.body, func.body);
+    }
+}
+
+#[test]
+fn test_expr_parse() {
+    use crate::expr::Expr;
+
+    let expr = Expr::parse("log(2, x) + g(x, 1) * -y^2").unwrap();
+    assert_eq!(expr.to_string(), "log(2,x)+g(x,1)*-y^2");
+    assert_eq!(Expr::parse(&expr.to_string()).unwrap(), expr);
+
+    // 后面的参数为负时，输出的文本重新解析后仍得到同一棵树
+    for text in ["f(x,-y)", "log(2,-x)+g(-x,-2,y)", "f(x,-(y+1))*-h(-x)"] {
+        let expr = Expr::parse(text).unwrap();
+        assert_eq!(Expr::parse(&expr.to_string()).unwrap(), expr, "{}", text);
+    }
+
+    let err = Expr::parse("x + )").unwrap_err();
+    assert!(matches!(err, CalcError::Parse { span, .. } if span.start == 4));
+    assert!(Expr::parse("x y").is_err());
+}
+
+#[test]
+fn test_overload_by_arity() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    for text in ["norm(x,y)=(x^2+y^2)^0.5", "norm(x,y,z)=(x^2+y^2+z^2)^0.5"] {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        Function::new(&tokens, function_table.clone()).unwrap();
+    }
+
+    let mut binding = function_table.borrow_mut();
+    assert_eq!(binding.len(), 2);
+    assert!(binding.contains("norm", 2) && binding.contains("norm", 3));
+    assert!(!binding.contains("norm", 1));
+
+    let norm3 = binding.find("norm", 3).unwrap();
+    assert_eq!(norm3.caculate(&[2.0, 3.0, 6.0], function_table.clone()).unwrap(), 7.0);
+
+    assert!(binding.remove("norm", 2).is_some());
+    assert_eq!(binding.iter().map(|f| f.arity()).collect::<Vec<_>>(), vec![3]);
+}
+
+#[test]
+fn test_redefine_and_delete() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+
+    define(&function_table, "f(x)=x+1", DefineMode::Strict).unwrap();
+    define(&function_table, "g(x)=f(x)*2", DefineMode::Strict).unwrap();
+    assert!(define(&function_table, "f(x)=x+2", DefineMode::Strict).is_err());
+
+    // 替换 f 后，g 在求值时使用新的定义
+    define(&function_table, "f(x)=x+2", DefineMode::Replace).unwrap();
+    {
+        let binding = function_table.borrow();
+        let g = binding.find("g", 1).unwrap();
+        assert_eq!(g.caculate(&[1.0], function_table.clone()).unwrap(), 6.0);
+        assert_eq!(binding.dependents("f", 1), vec![("g".to_string(), 1)]);
+    }
+
+    // 不允许通过替换制造循环调用
+    assert!(define(&function_table, "f(x)=g(x)", DefineMode::Replace).is_err());
+
+    // 只交换参数顺序也是新的定义
+    define(&function_table, "h(x,y)=x-y", DefineMode::Strict).unwrap();
+    assert!(matches!(
+        define(&function_table, "h(y,x)=x-y", DefineMode::Strict),
+        Err(CalcError::Redefinition { arity: 2, .. })
+    ));
+    let h = define(&function_table, "h(y,x)=x-y", DefineMode::Replace).unwrap();
+    assert_eq!(h.caculate(&[1.0, 0.0], function_table.clone()).unwrap(), -1.0);
+    assert_eq!(function_table.borrow().find("h", 2), Some(&h));
+    function_table.borrow_mut().delete("h", 2).unwrap();
+
+    let mut binding = function_table.borrow_mut();
+    assert!(binding.delete("f", 1).is_err());
+    assert!(binding.delete("g", 1).is_ok());
+    assert!(binding.delete("f", 1).is_ok());
+    assert!(binding.is_empty());
+}
+
+#[test]
+fn test_chain_rule() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    for text in ["f(x)=x^3", "h(u,v)=u*v^2", "g(x)=f(x)^2", "k(x)=h(x^2, sin(x))"] {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        Function::new(&tokens, function_table.clone()).unwrap();
+    }
+    let x = "x".to_string();
+    let at = |f: &Function, v: f64| f.caculate(&[v], function_table.clone()).unwrap();
+
+    // g = x^6, g' = 6x^5
+    let g = function_table.borrow().find("g", 1).cloned().unwrap();
+    let dg = g.derivative_with_mode(&x, function_table.clone(), DerivativeMode::Inline).unwrap();
+    assert!((at(&dg, 2.0) - 192.0).abs() < 1e-9);
+    let dg = g.derivative_with_mode(&x, function_table.clone(), DerivativeMode::Symbolic).unwrap();
+    assert!(dg.to_string().contains("f_x(x)"));
+    assert!(function_table.borrow().contains("f_x", 1));
+    // 与 partial 使用同一套名字和缓存
+    let f = function_table.borrow().find("f", 1).cloned().unwrap();
+    let fxx = f.partial_derivative(&[x.clone(), x.clone()], function_table.clone()).unwrap();
+    assert_eq!(fxx.name(), "f_xx");
+    assert!((at(&fxx, 2.0) - 12.0).abs() < 1e-9);
+    assert!((at(&dg, 2.0) - 192.0).abs() < 1e-9);
+
+    // k = x^2 * sin(x)^2, k' = 2x sin^2 x + 2x^2 sin x cos x
+    let k = function_table.borrow().find("k", 1).cloned().unwrap();
+    let expected = |x: f64| 2.0 * x * x.sin().powi(2) + 2.0 * x * x * x.sin() * x.cos();
+    for mode in [DerivativeMode::Inline, DerivativeMode::Symbolic] {
+        let dk = k.derivative_with_mode(&x, function_table.clone(), mode).unwrap();
+        assert!((at(&dk, 1.3) - expected(1.3)).abs() < 1e-9);
+    }
+    assert!(function_table.borrow().contains("h_u", 2));
+    assert!(function_table.borrow().contains("h_v", 2));
+
+    // 生成的 f_x 随 f 的修改失效，重新求导时使用新定义
+    let tokens = Tokenlizer::new("f(x)=x^4").tokenlize().unwrap();
+    Function::new_with_mode(&tokens, function_table.clone(), DefineMode::Replace).unwrap();
+    assert!(!function_table.borrow().contains("f_x", 1));
+    let dg = g.derivative_with_mode(&x, function_table.clone(), DerivativeMode::Symbolic).unwrap();
+    assert!((at(&dg, 2.0) - 1024.0).abs() < 1e-9);
+
+    // 不覆盖用户定义的同名函数
+    for text in ["q(x)=x^2", "q_x(x)=7", "r(x)=q(x)+1"] {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        Function::new(&tokens, function_table.clone()).unwrap();
+    }
+    let r = function_table.borrow().find("r", 1).cloned().unwrap();
+    assert!(matches!(
+        r.derivative_with_mode(&x, function_table.clone(), DerivativeMode::Symbolic),
+        Err(CalcError::Redefinition { name, .. }) if name == "q_x"
+    ));
+    assert_eq!(at(&function_table.borrow().find("q_x", 1).cloned().unwrap(), 1.0), 7.0);
+}
+
+#[test]
+fn test_mixed_partial() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    let tokens = Tokenlizer::new("f(x,y)=x^3*y^2+sin(y)").tokenlize().unwrap();
+    let f = Function::new(&tokens, function_table.clone()).unwrap();
+    let vars = |s: &str| s.chars().map(|c| c.to_string()).collect::<Vec<_>>();
+
+    // f_xxy = 12xy
+    let fxxy = f.partial_derivative(&vars("xxy"), function_table.clone()).unwrap();
+    assert_eq!(fxxy.name(), "f_xxy");
+    let v = fxxy.caculate(&[2.0, 3.0], function_table.clone()).unwrap();
+    assert!((v - 72.0).abs() < 1e-9);
+
+    // 中间结果 f_x、f_xx 已缓存，并可以当作普通函数调用
     {
-        for i in 0..tokens.len() {
-            println!("{}", tokens[i])
+        let binding = function_table.borrow();
+        let key = f.key();
+        assert!(binding.cached_partial(&key, &vars("x")).is_some());
+        assert!(binding.cached_partial(&key, &vars("xx")).is_some());
+        assert!(binding.contains("f_xx", 2));
+    }
+
+    // 重新定义 f 后缓存失效
+    let tokens = Tokenlizer::new("f(x,y)=x*y").tokenlize().unwrap();
+    Function::new_with_mode(&tokens, function_table.clone(), DefineMode::Replace).unwrap();
+    assert!(!function_table.borrow().contains("f_xx", 2));
+
+    assert!(f.partial_derivative(&vars("z"), function_table.clone()).is_err());
+
+    // 仍被其他函数调用的偏导数按新定义重新求出
+    let s = define(&function_table, "s(x,y)=x*y", DefineMode::Strict).unwrap();
+    s.partial_derivative(&vars("xy"), function_table.clone()).unwrap();
+    let u = define(&function_table, "u(x,y)=s_xy(x,y)+1", DefineMode::Strict).unwrap();
+    define(&function_table, "s(x,y)=x*y*2", DefineMode::Replace).unwrap();
+    assert_eq!(u.caculate(&[1.0, 1.0], function_table.clone()).unwrap(), 3.0);
+    assert!(function_table.borrow().contains("s_x", 2));
+
+    // 参数改名后无法再求出 s_xy，拒绝替换并报告求导时的错误
+    assert!(matches!(
+        define(&function_table, "s(a,b)=a*b", DefineMode::Replace),
+        Err(CalcError::UnknownVariable(name)) if name == "x"
+    ));
+    assert_eq!(u.caculate(&[1.0, 1.0], function_table.clone()).unwrap(), 3.0);
+    define(&function_table, "t(x,y,z)=x*y*z", DefineMode::Strict)
+        .unwrap()
+        .partial_derivative(&vars("xy"), function_table.clone())
+        .unwrap();
+    define(&function_table, "v(x,y,z)=t_xy(x,y,z)", DefineMode::Strict).unwrap();
+    let err = define(&function_table, "t(x,y,zz)=x*y*zz", DefineMode::Replace).unwrap_err();
+    assert_eq!(err.to_string(), "t_xy/3 is still called but would be renamed to t_x_y");
+
+    // 不同的求导序列得到不同的名字
+    let w = define(&function_table, "w(x,y,xy)=x^2*y*xy", DefineMode::Strict).unwrap();
+    let by_xy = w
+        .partial_derivative(&["xy".to_string()], function_table.clone())
+        .unwrap();
+    let by_x_y = w.partial_derivative(&vars("xy"), function_table.clone()).unwrap();
+    assert_eq!((by_xy.name(), by_x_y.name()), ("w_xy", "w_x_y"));
+    let v = define(&function_table, "v(x_1,y)=x_1*y", DefineMode::Strict).unwrap();
+    let by_x1 = v
+        .partial_derivative(&["x_1".to_string()], function_table.clone())
+        .unwrap();
+    assert_eq!(by_x1.name(), "v_1");
+
+    // 不覆盖用户定义的同名函数
+    define(&function_table, "k_x(x)=7", DefineMode::Strict).unwrap();
+    let k = define(&function_table, "k(x)=x^2", DefineMode::Strict).unwrap();
+    assert!(matches!(
+        k.partial_derivative(&vars("x"), function_table.clone()),
+        Err(CalcError::Redefinition { name, arity: 1 }) if name == "k_x"
+    ));
+    let k_x = function_table.borrow().find("k_x", 1).cloned().unwrap();
+    assert_eq!(k_x.caculate(&[2.0], function_table.clone()).unwrap(), 7.0);
+}
+
+#[test]
+fn test_gradient_jacobian_hessian() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    let mut funcs = Vec::new();
+    for text in ["f(x,y)=x^2*y+y^3", "g(x,y)=x*y"] {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        funcs.push(Function::new(&tokens, function_table.clone()).unwrap());
+    }
+    let point = vec![1.0, 2.0];
+
+    let grad = funcs[0].gradient(function_table.clone()).unwrap();
+    assert_eq!(grad.to_string(), "grad f(x,y)=[2*x*y, x^2+3*y^2]");
+    assert_eq!(grad.caculate(&point, function_table.clone()).unwrap(), vec![4.0, 13.0]);
+
+    let hess = funcs[0].hessian(function_table.clone()).unwrap();
+    assert_eq!(
+        hess.caculate(&point, function_table.clone()).unwrap(),
+        vec![vec![4.0, 2.0], vec![2.0, 12.0]]
+    );
+    assert_eq!(hess.rows()[1][0], hess.rows()[0][1]);
+
+    let jac = Function::jacobian(&funcs, function_table.clone()).unwrap();
+    assert_eq!(
+        jac.caculate(&point, function_table.clone()).unwrap(),
+        vec![vec![4.0, 13.0], vec![2.0, 1.0]]
+    );
+}
+
+#[test]
+fn test_integral() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    let x = "x".to_string();
+    let cases = [
+        "a(x)=3*x^2+2*x-5",
+        "b(x)=(2*x+1)^-1",
+        "c(x)=1/(3*x-1)^2",
+        "d(x)=2^(3*x)+log(10,2*x)",
+        "k(x)=sin(2*x)+cos(x)-tan(x/2)+sec(x)+csc(x)+cot(x)",
+        "m(x)=arctan(x)+arcsin(x/2)+arccos(x/3)",
+        "n(x)=a(x)*2",
+    ];
+    for text in cases {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        let f = Function::new(&tokens, function_table.clone()).unwrap();
+        let int_f = f.integral(&x, function_table.clone()).unwrap();
+
+        // 积分结果求导后应与被积函数一致
+        let back = int_f.derivative(&x, function_table.clone()).unwrap();
+        for v in [0.3, 0.7, 1.1] {
+            let expected = f.caculate(&[v], function_table.clone()).unwrap();
+            let got = back.caculate(&[v], function_table.clone()).unwrap();
+            assert!((expected - got).abs() < 1e-9, "{}: {} vs {}", text, expected, got);
         }
     }
 
-    match Function::new(&tokens, function_table.clone()) {
-        Ok(res) => res,
-        Err(e) => {
-            println!("Error: {}", e);
-            return;
+    let tokens = Tokenlizer::new("p(x)=sin(x^2)").tokenlize().unwrap();
+    let p = Function::new(&tokens, function_table.clone()).unwrap();
+    let err = p.integral(&x, function_table.clone()).unwrap_err();
+    assert!(err.to_string().contains("no closed form found"));
+}
+
+#[test]
+fn test_integration_strategies() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    let x = "x".to_string();
+    let cases = [
+        // 换元积分
+        "s1(x)=2*x*(x^2+1)^3",
+        "s2(x)=cos(x)*sin(x)^2",
+        "s3(x)=x/(x^2+1)",
+        "s4(x)=x*2^(x^2)",
+        // 分部积分
+        "p1(x)=x*sin(x)",
+        "p2(x)=x^2*2^x",
+        "p3(x)=x*log(10,x)",
+        "p4(x)=arctan(x^2)",
+        // 部分分式
+        "q1(x)=1/(x^2-1)",
+        "q2(x)=(x^3+1)/(x^2+3*x+2)",
+        "q3(x)=(2*x+3)/(x^2+2*x+5)",
+        "q4(x)=1/((x-1)^2*(x+2))",
+    ];
+    for text in cases {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        let f = Function::new(&tokens, function_table.clone()).unwrap();
+        let int_f = f
+            .integral(&x, function_table.clone())
+            .unwrap_or_else(|e| panic!("{}: {}", text, e));
+
+        let back = int_f.derivative(&x, function_table.clone()).unwrap();
+        for v in [1.3, 2.1, 3.7] {
+            let expected = f.caculate(&[v], function_table.clone()).unwrap();
+            let got = back.caculate(&[v], function_table.clone()).unwrap();
+            assert!(
+                (expected - got).abs() < 1e-6 * (1.0 + expected.abs()),
+                "{}: {} vs {}",
+                text,
+                expected,
+                got
+            );
         }
+    }
+
+    // 很小但不为零的系数不能被舍掉
+    let tokens = Tokenlizer::new("r(x)=(1e-9*x+1)/(x^2+1)").tokenlize().unwrap();
+    let r = Function::new(&tokens, function_table.clone()).unwrap();
+    let int_r = r.integral(&x, function_table.clone()).unwrap();
+    assert!(int_r.body.to_string().contains("log(e,x^2+1)"), "{}", int_r);
+    let back = int_r.derivative(&x, function_table.clone()).unwrap();
+    let expected = r.caculate(&[2.0], function_table.clone()).unwrap();
+    let got = back.caculate(&[2.0], function_table.clone()).unwrap();
+    assert!((expected - got).abs() < 1e-15, "{} vs {}", expected, got);
+}
+
+#[test]
+fn test_definite_integral() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    let x = "x".to_string();
+    let options = QuadratureOptions::default();
+    let cases = [
+        ("a(x)=x^2", 0.0, 1.0, 1.0 / 3.0),
+        ("b(x)=sin(x)", 0.0, std::f64::consts::PI, 2.0),
+        ("c(x)=x", 1.0, 0.0, -0.5),
+        // 端点奇异
+        ("d(x)=x^-0.5", 0.0, 1.0, 2.0),
+        ("g(x)=log(2,x)", 0.0, 1.0, -1.0 / std::f64::consts::LN_2),
+        // 无穷区间
+        ("h(x)=1/(1+x^2)", 0.0, f64::INFINITY, std::f64::consts::FRAC_PI_2),
+        ("k(x)=1/(1+x^2)", f64::NEG_INFINITY, f64::INFINITY, std::f64::consts::PI),
+        ("m(x)=2^x", f64::NEG_INFINITY, 0.0, 1.0 / std::f64::consts::LN_2),
+    ];
+    for (text, a, b, expected) in cases {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        let f = Function::new(&tokens, function_table.clone()).unwrap();
+        let res = f
+            .definite_integral(&x, a, b, &[], &options, function_table.clone())
+            .unwrap();
+        assert!((res.value - expected).abs() < 1e-8, "{}: {}", text, res);
+        assert!(res.error < 1e-8 && res.evaluations > 0);
+    }
+
+    // 其余参数取固定值：∫_0^2 x*y dx, y = 3
+    let tokens = Tokenlizer::new("p(x,y)=x*y").tokenlize().unwrap();
+    let p = Function::new(&tokens, function_table.clone()).unwrap();
+    let res = p
+        .definite_integral(&x, 0.0, 2.0, &[3.0], &options, function_table.clone())
+        .unwrap();
+    assert!((res.value - 6.0).abs() < 1e-10);
+
+    let tokens = Tokenlizer::new("q(x)=1/x").tokenlize().unwrap();
+    let q = Function::new(&tokens, function_table.clone()).unwrap();
+    assert!(
+        q.definite_integral(&x, -1.0, 1.0, &[], &options, function_table.clone())
+            .is_err()
+    );
+}
+
+#[test]
+fn test_multiple_integral() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    let options = QuadratureOptions::default();
+    let var = |name: &str| Expr::Var(name.to_string());
+
+    let tokens = Tokenlizer::new("f(x,y)=x*y").tokenlize().unwrap();
+    let f = Function::new(&tokens, function_table.clone()).unwrap();
+
+    // 矩形区域：∫_0^1 ∫_0^2 x*y dy dx = 1
+    let rect = [
+        Limits::new("x", Expr::Const(0.0), Expr::Const(1.0)),
+        Limits::new("y", Expr::Const(0.0), Expr::Const(2.0)),
+    ];
+    let res = f
+        .multiple_integral(&rect, &[], CubatureMethod::Adaptive, &options, function_table.clone())
+        .unwrap();
+    assert!((res.value - 1.0).abs() < 1e-10);
+
+    // 三角形区域：∫_0^1 ∫_0^x x*y dy dx = 1/8
+    let triangle = [
+        Limits::new("x", Expr::Const(0.0), Expr::Const(1.0)),
+        Limits::new("y", Expr::Const(0.0), var("x")),
+    ];
+    let res = f
+        .multiple_integral(&triangle, &[], CubatureMethod::Adaptive, &options, function_table.clone())
+        .unwrap();
+    assert!((res.value - 0.125).abs() < 1e-10);
+
+    // 内层上下限不能依赖内层变量
+    let bad = [
+        Limits::new("x", Expr::Const(0.0), var("y")),
+        Limits::new("y", Expr::Const(0.0), Expr::Const(1.0)),
+    ];
+    assert!(
+        f.multiple_integral(&bad, &[], CubatureMethod::Adaptive, &options, function_table.clone())
+            .is_err()
+    );
+
+    // 三重积分：∫∫∫_[0,1]^3 x+y+z = 3/2
+    let tokens = Tokenlizer::new("g(x,y,z)=x+y+z").tokenlize().unwrap();
+    let g = Function::new(&tokens, function_table.clone()).unwrap();
+    let cube = [
+        Limits::new("x", Expr::Const(0.0), Expr::Const(1.0)),
+        Limits::new("y", Expr::Const(0.0), Expr::Const(1.0)),
+        Limits::new("z", Expr::Const(0.0), Expr::Const(1.0)),
+    ];
+    let res = g
+        .multiple_integral(&cube, &[], CubatureMethod::Adaptive, &options, function_table.clone())
+        .unwrap();
+    assert!((res.value - 1.5).abs() < 1e-10);
+
+    // Monte Carlo：相同种子结果相同
+    let mc = CubatureMethod::MonteCarlo {
+        samples: 20000,
+        seed: 42,
     };
+    let first = g
+        .multiple_integral(&cube, &[], mc, &options, function_table.clone())
+        .unwrap();
+    let second = g
+        .multiple_integral(&cube, &[], mc, &options, function_table.clone())
+        .unwrap();
+    assert_eq!(first, second);
+    assert!((first.value - 1.5).abs() < 4.0 * first.error);
+
+    let sobol = CubatureMethod::QuasiMonteCarlo { samples: 4096 };
+    let res = f
+        .multiple_integral(&triangle, &[], sobol, &options, function_table.clone())
+        .unwrap();
+    assert!((res.value - 0.125).abs() < 1e-3);
 }
 
-pub fn caculate(function_table: Rc<RefCell<FunctionTable>>) {
-    let mut input = String::new();
-    stdin().read_line(&mut input).unwrap();
+#[test]
+fn test_compile() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    let defs = [
+        "f(x)=log(2,x)+3^(2*x+1)",
+        "g(x,y)=-x^2*sin(y)+cot(x)/sec(y)",
+        "h(x,y)=f(x*y)-g(y,x)^2+arctan(f(y))",
+    ];
+    let mut funcs = Vec::new();
+    for text in defs {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        funcs.push(Function::new(&tokens, function_table.clone()).unwrap());
+    }
 
-    let mut vec: Vec<&str> = input.trim().split(|c| c == '(' || c == ')').collect();
-    let name = vec[0];
+    let h = &funcs[2];
+    let compiled = h.compile(function_table.clone()).unwrap();
+    assert_eq!(compiled.arity(), 2);
 
-    vec = vec[1].split(|c| c == ',' || c == ' ').collect();
+    // 编译后的结果与逐节点求值一致，求值栈可以复用
+    let mut stack = Vec::new();
+    for point in [[0.5, 1.5], [1.2, 0.3], [2.0, 2.0]] {
+        let expected = h.caculate(&point, function_table.clone()).unwrap();
+        let got = compiled.caculate_with(&point, &mut stack).unwrap();
+        assert!((expected - got).abs() < 1e-12, "{} vs {}", expected, got);
+    }
 
-    let binding = function_table.borrow();
-    let func = binding.find(name, vec.len());
-    if func == None {
-        println!("no such function: {}", name);
-        return;
-    } else {
-        if let Some(x) = func {
-            let mut args = Vec::<f64>::new();
-            for i in 0..vec.len() {
-                args.push(vec[i].parse::<f64>().unwrap());
-            }
+    assert!(compiled.caculate(&[1.0]).is_err());
 
-            let ans = match x.caculate(&args, function_table.clone()) {
-                Ok(v) => v,
-                Err(e) => {
-                    println!("error: {}", e);
-                    return;
-                }
-            };
-            println!("{}={}", input.trim(), ans);
-        }
+    // 编译时即解析变量
+    let tokens = Tokenlizer::new("k(x)=x+1").tokenlize().unwrap();
+    let mut k = Function::new(&tokens, function_table.clone()).unwrap();
+    k.body = Expr::Var("y".to_string());
+    assert!(k.compile(function_table.clone()).is_err());
+}
+
+#[test]
+fn test_batch() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+    let tokens = Tokenlizer::new("f(x,y)=x^2+sin(y)").tokenlize().unwrap();
+    let f = Function::new(&tokens, function_table.clone()).unwrap();
+
+    let xs: Vec<f64> = (0..1000).map(|i| i as f64 * 0.01).collect();
+    let ys: Vec<f64> = (0..1000).map(|i| 1.0 - i as f64 * 0.002).collect();
+    let columns = [xs.as_slice(), ys.as_slice()];
+
+    let serial = f
+        .caculate_batch(&columns, BatchMode::Serial, function_table.clone())
+        .unwrap();
+    let parallel = f
+        .caculate_batch(
+            &columns,
+            BatchMode::Parallel { threads: 4 },
+            function_table.clone(),
+        )
+        .unwrap();
+    assert_eq!(serial, parallel);
+    for i in [0, 123, 999] {
+        let expected = f
+            .caculate(&[xs[i], ys[i]], function_table.clone())
+            .unwrap();
+        assert_eq!(serial[i], expected);
     }
+
+    // 列数或列长不一致时报错
+    assert!(
+        f.caculate_batch(&columns[..1], BatchMode::Serial, function_table.clone())
+            .is_err()
+    );
+    assert!(
+        f.caculate_batch(
+            &[xs.as_slice(), &ys[..10]],
+            BatchMode::Serial,
+            function_table.clone()
+        )
+        .is_err()
+    );
 }
 
-pub fn derivative(function_table: Rc<RefCell<FunctionTable>>) {
-    let mut input = String::new();
-    stdin().read_line(&mut input).unwrap();
+#[test]
+fn test_shared_table() {
+    use crate::function::shared::SharedFunctionTable;
 
-    let mut vec: Vec<&str> = input.trim().split(|c| c == '(' || c == ')').collect();
-    let name = vec[0];
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SharedFunctionTable>();
+    assert_send_sync::<FunctionTable>();
+    assert_send_sync::<Function>();
 
-    vec = vec[1].split(|c| c == ',' || c == ' ').collect();
+    let table = SharedFunctionTable::new();
+    for text in ["f(x)=x^2", "g(x,y)=f(x)+y"] {
+        let tokens = Tokenlizer::new(text).tokenlize().unwrap();
+        table.define(&tokens, DefineMode::Strict).unwrap();
+    }
 
-    let mut binding = function_table.borrow_mut();
-    let func = binding.find(name, vec.len());
-    if func == None {
-        println!("no such function: {}", name);
-        return;
-    } else {
-        if let Some(x) = func {
-            input = String::new();
-            stdin().read_line(&mut input).unwrap();
+    let compiled = table.compile("g", 2).unwrap();
+    let results: Vec<f64> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let table = table.clone();
+                let compiled = &compiled;
+                s.spawn(move || {
+                    let x = i as f64;
+                    let a = table.caculate("g", &[x, 1.0]).unwrap();
+                    let b = compiled.caculate(&[x, 1.0]).unwrap();
+                    assert_eq!(a, b);
+                    a
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(results, vec![1.0, 2.0, 5.0, 10.0]);
 
-            let dx = input;
+    // 快照不受之后的修改影响
+    let snapshot = table.snapshot();
+    let tokens = Tokenlizer::new("f(x)=x^3").tokenlize().unwrap();
+    table.define(&tokens, DefineMode::Replace).unwrap();
+    assert_eq!(table.caculate("g", &[2.0, 0.0]).unwrap(), 8.0);
+    let g = snapshot.find("g", 2).unwrap();
+    assert_eq!(g.caculate_in(&[2.0, 0.0], &snapshot).unwrap(), 4.0);
+}
 
-            let _f = match x.derivative(&dx, function_table.clone()) {
-                Ok(v) => v,
-                Err(e) => {
-                    println!("{}", e);
-                    return;
-                }
-            };
-            if let Expr::Func(ref name, _) = _f.symble {
-                binding.insert(name.to_string(), _f.clone());
-            }
-            println!("{}", _f);
-        }
+#[test]
+fn test_error_kinds() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+
+    let err = define(&function_table, "f(x)=g(x)+1", DefineMode::Strict).unwrap_err();
+    assert!(
+        matches!(&err, CalcError::UnknownFunction { name, arity: 1, span: Some(span) }
+            if name == "g" && span.start == 5)
+    );
+
+    let f = define(&function_table, "f(x)=x^2", DefineMode::Strict).unwrap();
+    define(&function_table, "g(x)=f(x)+1", DefineMode::Strict).unwrap();
+    assert!(matches!(
+        define(&function_table, "f(x)=x^3", DefineMode::Strict),
+        Err(CalcError::Redefinition { arity: 1, .. })
+    ));
+    assert!(matches!(
+        define(&function_table, "f(x)=g(x)", DefineMode::Replace),
+        Err(CalcError::RecursiveDefinition { .. })
+    ));
+    assert!(matches!(
+        function_table.borrow_mut().delete("f", 1),
+        Err(CalcError::InUse { dependents, .. }) if dependents == vec![("g".to_string(), 1)]
+    ));
+
+    assert_eq!(
+        f.caculate(&[1.0, 2.0], function_table.clone()),
+        Err(CalcError::ArityMismatch {
+            expected: 1,
+            found: 2
+        })
+    );
+    assert!(matches!(
+        f.definite_integral(
+            &"y".to_string(),
+            0.0,
+            1.0,
+            &[],
+            &QuadratureOptions::default(),
+            function_table.clone()
+        ),
+        Err(CalcError::UnknownVariable(name)) if name == "y"
+    ));
+
+    let p = define(&function_table, "p(x)=sin(x^2)", DefineMode::Strict).unwrap();
+    assert!(matches!(
+        p.integral(&"x".to_string(), function_table.clone()),
+        Err(CalcError::NoClosedForm(_))
+    ));
+
+    let q = define(&function_table, "q(x)=1/x", DefineMode::Strict).unwrap();
+    let res = q.definite_integral(
+        &"x".to_string(),
+        -1.0,
+        1.0,
+        &[],
+        &QuadratureOptions::default(),
+        function_table.clone(),
+    );
+    assert!(matches!(res, Err(CalcError::Domain(_))));
+}
+
+#[test]
+fn test_checked_evaluation() {
+    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
+
+    let g = define(&function_table, "g(x)=arcsin(x)+1", DefineMode::Strict).unwrap();
+    let f = define(&function_table, "f(x,y)=x/y+log(2,x)", DefineMode::Strict).unwrap();
+    let h = define(&function_table, "h(x)=2*g(x)", DefineMode::Strict).unwrap();
+
+    // 宽松模式保持 IEEE 行为
+    let v = f.caculate(&[1.0, 0.0], function_table.clone()).unwrap();
+    assert!(v.is_infinite());
+    let v = g.caculate(&[2.0], function_table.clone()).unwrap();
+    assert!(v.is_nan());
+
+    assert_eq!(
+        f.caculate_with_mode(&[1.0, 0.0], function_table.clone(), EvalMode::Checked),
+        Err(CalcError::NonFinite {
+            function: ("f".to_string(), 2),
+            path: vec![0],
+            expr: "x/y".to_string(),
+            operands: vec![1.0, 0.0],
+            value: f64::INFINITY,
+        })
+    );
+
+    let Err(CalcError::NonFinite {
+        function,
+        path,
+        operands,
+        value,
+        ..
+    }) = f.caculate_with_mode(&[-1.0, 1.0], function_table.clone(), EvalMode::Checked)
+    else {
+        panic!("log(2,-1) should be reported");
+    };
+    assert_eq!(function, ("f".to_string(), 2));
+    assert_eq!(path, vec![1]);
+    assert_eq!(operands, vec![2.0, -1.0]);
+    assert!(value.is_nan());
+
+    // 被调函数内部的错误指向被调函数中的位置
+    let Err(CalcError::NonFinite { function, path, .. }) =
+        h.caculate_with_mode(&[2.0], function_table.clone(), EvalMode::Checked)
+    else {
+        panic!("arcsin(2) should be reported");
+    };
+    assert_eq!(function, ("g".to_string(), 1));
+    assert_eq!(path, vec![0]);
+
+    assert_eq!(
+        h.caculate_with_mode(&[0.5], function_table.clone(), EvalMode::Checked),
+        h.caculate(&[0.5], function_table.clone())
+    );
+}
+
+#[test]
+fn test_session() {
+    use crate::repl::{Outcome, Session};
+
+    let mut session = Session::new();
+    let mut run = |line: &str| match session.execute(line) {
+        Ok(Outcome::Output(text)) => Ok(text),
+        Ok(Outcome::Quit) => Ok("quit".to_string()),
+        Err(e) => Err(e),
+    };
+
+    assert_eq!(run("def f(x)=x^2").unwrap(), "f(x)=x^2");
+    run("def f(x,y)=x*y").unwrap();
+    run("  # 注释和空行不做任何事").unwrap();
+    assert_eq!(run("").unwrap(), "");
+    assert_eq!(run("eval f(3)+f(2,5)").unwrap(), "f(3)+f(2,5)=19");
+    assert_eq!(run("diff f(x) x").unwrap(), "f'(x)=2*x");
+    assert_eq!(run("list").unwrap(), "f(x)=x^2\nf(x,y)=x*y\nf'(x)=2*x");
+    assert_eq!(run("show f(x)").unwrap(), "f(x)=x^2\n  used by f'/1");
+    // 对不同变量求导得到不同名字的函数，不会互相覆盖
+    assert_eq!(run("diff f(x,y) x").unwrap(), "f_x(x,y)=y");
+    assert_eq!(run("diff f(x,y) y").unwrap(), "f_y(x,y)=x");
+    assert_eq!(run("diff f(x) y").unwrap(), "f_y(x)=0");
+    assert_eq!(run("eval f'(3)+f_x(1,2)").unwrap(), "f'(3)+f_x(1,2)=8");
+    assert_eq!(run("quit").unwrap(), "quit");
+    assert_eq!(run("table f(x) x 0 1 0.5").unwrap(), "0\t0\n0.5\t0.25\n1\t1");
+    assert!(matches!(
+        run("table f(x) x 0 1e9 1e-9"),
+        Err(CalcError::InvalidArgument(_))
+    ));
+
+    // 积分结果按积分变量命名，求导和积分都不覆盖用户定义的函数
+    run("def k(x,y)=x*y").unwrap();
+    assert_eq!(run("int k x").unwrap(), "int_k_x(x,y)=x^2/2*y");
+    assert_eq!(run("int k y").unwrap(), "int_k_y(x,y)=x*(y^2/2)");
+    run("def m(x)=x").unwrap();
+    run("def m'(x)=7").unwrap();
+    assert!(matches!(run("diff m x"), Err(CalcError::Redefinition { .. })));
+    assert_eq!(run("eval m'(1)").unwrap(), "m'(1)=7");
+
+    // 只写函数名而函数有多个重载时必须写出参数
+    assert!(matches!(run("diff f x"), Err(CalcError::InvalidArgument(_))));
+    assert!(matches!(run("frobnicate"), Err(CalcError::InvalidArgument(_))));
+    assert!(matches!(
+        run("check 1/(f(1)-1)"),
+        Err(CalcError::NonFinite { value, .. }) if value.is_infinite()
+    ));
+
+    // 错误位置以整行为准
+    let err = run("def  g(x)=x+").unwrap_err();
+    assert!(matches!(err, CalcError::Parse { span, .. } if span.start == 12));
+    let err = run("def g(x)=h(x)").unwrap_err();
+    assert!(matches!(err, CalcError::UnknownFunction { span: Some(span), .. } if span.start == 9));
+    let err = run("multiquad f(x,y) x 0 1; y 0 x+").unwrap_err();
+    assert!(matches!(err, CalcError::Parse { span, .. } if span.start == 30));
+    let err = run("multiquad f(x,y) x 0 1; y  #  1").unwrap_err();
+    assert!(matches!(err, CalcError::Lex { span, .. } if span.start == 27));
+}
+
+#[test]
+fn test_script() {
+    use crate::repl::Session;
+
+    let script = "\
+# 多项式和它的导数
+def f(x)=x^3-2*x
+diff f x
+eval f'(2)
+
+  eval g(1)
+def h(x)=(x+
+eval f(1)
+";
+
+    let mut output = Vec::new();
+    let errors = Session::new().run_script(script, false, |text| output.push(text.to_string()));
+    assert_eq!(output, vec!["f(x)=x^3-2*x", "f'(x)=3*x^2-2", "f'(2)=10"]);
+    assert_eq!(errors.len(), 1);
+    assert_eq!((errors[0].line, errors[0].column), (6, 8));
+    assert!(matches!(errors[0].error, CalcError::UnknownFunction { .. }));
+
+    let mut output = Vec::new();
+    let errors = Session::new().run_script(script, true, |text| output.push(text.to_string()));
+    assert_eq!(output.last().unwrap(), "f(1)=-1");
+    assert_eq!(errors.len(), 2);
+    assert_eq!((errors[1].line, errors[1].column), (7, 13));
+    assert_eq!(
+        errors[1].render("model.calc"),
+        "model.calc:7:13: error: unexpected end of input\n  | def h(x)=(x+\n  |             ^"
+    );
+}
+
+#[test]
+fn test_workspace() {
+    use crate::repl::Session;
+
+    let mut session = Session::new();
+    for line in [
+        "def g(x)=2.718281828459045^x*sin(x)-pi",
+        "def f(x,y)=x^-2*g(y)/(1-x)^(1/3)",
+        "def p(e)=e+1",
+        "def q(x,e)=x*e",
+        "def r(x)=f(x,-x)",
+        "def h(x)=log(10,x)-(-3)*x+0.1",
+        "def u(x)=2^(3*x)+cos(x)^2",
+        "def v(x)=x/(x^2+1)",
+        "def k(x)=f(x,2)+-x",
+        "diff g x",
+        "diff k x symbolic",
+        "diff u x",
+        "int v x",
+        "partial f x y",
+        "grad f",
+    ] {
+        session.execute(line).unwrap();
     }
+    let function_table = session.function_table();
+
+    let text = function_table.borrow().to_workspace().unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), function_table.borrow().len());
+    // 被调用的函数写在调用它的函数之前
+    let position = |prefix: &str| lines.iter().position(|l| l.starts_with(prefix)).unwrap();
+    assert!(position("g(x)=") < position("f(x,y)="));
+    assert!(position("f(x,y)=") < position("k(x)="));
+    assert!(position("k(x)=") < position("k'(x)="));
+    // e 是普通的变量名，自然常数写成数字以免读回时与变量混淆
+    assert!(lines.contains(&"p(e)=e+1") && lines.contains(&"q(x,e)=x*e"));
+    assert!(lines.contains(&"r(x)=f(x,-x)"));
+    assert!(lines.contains(&"g(x)=2.718281828459045^x*sin(x)-3.141592653589793"));
+
+    let mut loaded = FunctionTable::new();
+    assert_eq!(loaded.load_workspace(&text).unwrap(), lines.len());
+    for func in function_table.borrow().iter() {
+        let (name, argc) = func.key();
+        assert_eq!(loaded.find(&name, argc), Some(func));
+    }
+    assert_eq!(loaded.len(), lines.len());
+
+    // 出错时报告行号，表保持不变
+    let mut table = FunctionTable::new();
+    let err = table
+        .load_workspace("a(x)=x\n\n# 注释\nb(x)=c(x)\n")
+        .unwrap_err();
+    assert!(matches!(err, CalcError::Workspace { line: 4, .. }));
+    assert!(table.is_empty());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    use crate::repl::Session;
+
+    let expr = Expr::parse("log(2,x)+-sin(f(x,1))").unwrap();
+    let json = serde_json::to_string(&expr).unwrap();
+    assert_eq!(
+        json,
+        concat!(
+            r#"{"op":"add","args":[{"op":"log","args":[{"op":"const","value":2.0},{"op":"var","name":"x"}]},"#,
+            r#"{"op":"neg","args":[{"op":"sin","args":[{"op":"call","name":"f","args":[{"op":"var","name":"x"},{"op":"const","value":1.0}]}]}]}]}"#
+        )
+    );
+    assert_eq!(serde_json::from_str::<Expr>(&json).unwrap(), expr);
+
+    // 常数原样保留，包括 e 和非有限值
+    for c in [std::f64::consts::E, 0.1 + 0.2, -0.0, f64::INFINITY, f64::NEG_INFINITY] {
+        let json = serde_json::to_string(&Expr::Const(c)).unwrap();
+        let Expr::Const(back) = serde_json::from_str(&json).unwrap() else {
+            panic!("expect a constant");
+        };
+        assert_eq!(back.to_bits(), c.to_bits());
+    }
+    let json = serde_json::to_string(&Expr::Const(f64::NAN)).unwrap();
+    assert_eq!(json, r#"{"op":"const","value":"nan"}"#);
+
+    for bad in [
+        r#"{"op":"add","args":[{"op":"var","name":"x"}]}"#,
+        r#"{"op":"var"}"#,
+        r#"{"op":"sqrt","args":[{"op":"var","name":"x"}]}"#,
+        r#"{"op":"var","name":"x","extra":1}"#,
+    ] {
+        assert!(serde_json::from_str::<Expr>(bad).is_err());
+    }
+
+    let mut session = Session::new();
+    for line in [
+        "def g(x)=2.718281828459045^x*sin(x)",
+        "def f(x,y)=x*g(y)",
+        "def h(x)=x*2.718281828459045^x",
+        "diff f x",
+        "int h x",
+    ] {
+        session.execute(line).unwrap();
+    }
+    let function_table = session.function_table();
+
+    let f = function_table.borrow().find("f", 2).cloned().unwrap();
+    let json = serde_json::to_string(&f).unwrap();
+    assert!(json.starts_with(r#"{"name":"f","params":["x","y"],"body":"#));
+    assert_eq!(serde_json::from_str::<Function>(&json).unwrap(), f);
+
+    let json = serde_json::to_string(&*function_table.borrow()).unwrap();
+    let mut table: FunctionTable = serde_json::from_str(&json).unwrap();
+    assert_eq!(table.len(), function_table.borrow().len());
+    for func in function_table.borrow().iter() {
+        let (name, argc) = func.key();
+        assert_eq!(table.find(&name, argc), Some(func));
+        assert_eq!(
+            table.dependencies(&name, argc),
+            function_table.borrow().dependencies(&name, argc)
+        );
+    }
+    // 导函数与原函数之间的依赖同样保留
+    assert!(matches!(table.delete("f", 2), Err(CalcError::InUse { .. })));
+
+    // 调用未定义的函数或循环调用的表被拒绝
+    let missing = r#"{"functions":[{"name":"a","params":["x"],"body":{"op":"call","name":"b","args":[{"op":"var","name":"x"}]}}]}"#;
+    assert!(serde_json::from_str::<FunctionTable>(missing).is_err());
+    let cycle = r#"{"functions":[
+        {"name":"a","params":["x"],"body":{"op":"call","name":"b","args":[{"op":"var","name":"x"}]}},
+        {"name":"b","params":["x"],"body":{"op":"call","name":"a","args":[{"op":"var","name":"x"}]}}]}"#;
+    assert!(serde_json::from_str::<FunctionTable>(cycle).is_err());
+}
+
+#[test]
+fn test_latex() {
+    use crate::repl::Session;
+
+    for (text, latex) in [
+        ("1/x^2", r"\frac{1}{x^{2}}"),
+        ("log(2.718281828459045,x)+log(2,x)", r"\ln\left(x\right) + \log_{2}\left(x\right)"),
+        ("sin(x)^2*arccos(x)", r"\sin^{2}\left(x\right) \cdot \arccos\left(x\right)"),
+        ("sin(x)^-1", r"\sin\left(x\right)^{-1}"),
+        ("x^(1/2)+(x+1)^0.5", r"\sqrt{x} + \sqrt{x + 1}"),
+        ("(a+b)*c-2*x+3*y^2", r"\left(a + b\right) \cdot c - 2x + 3y^{2}"),
+        ("(x/y)^2", r"\left(\frac{x}{y}\right)^{2}"),
+        ("x-(y-z)+-w", r"x - \left(y - z\right) - w"),
+        ("-x^2*pi", r"-x^{2} \cdot \pi"),
+        ("pi*x+pi*f(x)", r"\pi x + \pi f\left(x\right)"),
+        ("pi*x^2+pi*sin(x)", r"\pi x^{2} + \pi \sin\left(x\right)"),
+        ("2^x^y", r"2^{x^{y}}"),
+        ("f_xy(x1,y)*g''(e)", r"f_{xy}\left(x_{1}, y\right) \cdot g''\left(e\right)"),
+    ] {
+        assert_eq!(Expr::parse(text).unwrap().to_latex(), latex, "{}", text);
+    }
+
+    let mut session = Session::new();
+    session.execute("def f(x,y)=x^3*y").unwrap();
+    session.execute("partial f(x,y) x").unwrap();
+    let function_table = session.function_table();
+    let f_x = function_table.borrow().find("f_x", 2).cloned().unwrap();
+    assert_eq!(
+        f_x.to_latex(),
+        format!(r"f_{{x}}\left(x, y\right) = {}", f_x.body.to_latex())
+    );
+
+    session.execute("def g(x)=x^3").unwrap();
+    session.execute("diff g x").unwrap();
+    let dg = function_table.borrow().find("g'", 1).cloned().unwrap();
+    assert_eq!(dg.to_latex(), r"g'\left(x\right) = 3x^{2}");
+}
+
+#[test]
+fn test_pretty() {
+    use crate::{
+        pretty::Style,
+        repl::{Outcome, Session},
+    };
+
+    for (text, unicode) in [
+        ("3^(2*x+1)*log(3,e)*2", "3^(2x + 1)·log₃(e)·2"),
+        ("x^2-3*x^-12+-pi", "x² − 3x⁻¹² − π"),
+        ("(x+1)^(1/2)/x^0.5", "√(x + 1)/√x"),
+        ("sin(x)^2+cos(x)^-1", "sin²(x) + cos(x)⁻¹"),
+        ("(-2)^x*-y", "(−2)^x·−y"),
+        ("log(x,2)+f(x,y)", "log(x, 2) + f(x, y)"),
+    ] {
+        assert_eq!(Expr::parse(text).unwrap().to_unicode(), unicode, "{}", text);
+    }
+
+    let expr = Expr::parse("(x^2+1)/(y-x)^(1/2)+e^(x/2)").unwrap();
+    assert_eq!(
+        expr.render(Style::TwoD),
+        [
+            "             x",
+            "  2         ───",
+            " x  + 1      2",
+            "──────── + e",
+            "  _____",
+            " √y − x",
+        ]
+        .join("\n")
+    );
+
+    let expr = Expr::parse("((x+1)/(x-1))^3").unwrap();
+    assert_eq!(
+        expr.render(Style::TwoD),
+        ["         3", "⎛ x + 1 ⎞", "⎜───────⎟", "⎝ x − 1 ⎠"].join("\n")
+    );
+
+    let mut session = Session::new();
+    session.execute("def f(x)=x^3").unwrap();
+    session.execute("set style unicode").unwrap();
+    assert_eq!(session.style(), Style::Unicode);
+    assert_eq!(
+        session.execute("diff f x"),
+        Ok(Outcome::Output("f'(x) = 3x²".to_string()))
+    );
+    assert!(session.execute("set style fancy").is_err());
 }
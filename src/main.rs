@@ -1,25 +1,114 @@
-use std::{cell::RefCell, io::stdin, rc::Rc};
-
-use calculus::function::FunctionTable;
-
-fn main() {
-    let function_table = Rc::new(RefCell::new(FunctionTable::new()));
-    let mut input;
-
-    loop{
-        input=String::new();
-        stdin().read_line(& mut input).unwrap();
-        if input.trim()=="put"{
-            calculus::put(function_table.clone());
-        }else if input.trim()=="caculate"{
-            calculus::caculate(function_table.clone());
-        }else if input.trim()=="derivative"{
-            calculus::derivative(function_table.clone());
-        }else if input.trim()=="stop"{
-            break;
-        }else{
-            println!("unknown command");
+use std::{path::PathBuf, process::ExitCode};
+
+use calculus::{
+    error::render_error,
+    repl::{Outcome, Session},
+};
+use rustyline::{DefaultEditor, error::ReadlineError};
+
+/// 历史记录保存在主目录下的 `.calculus_history`。
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".calculus_history"))
+}
+
+/// `calculus` 启动交互式界面，`calculus run <file> [--keep-going]` 执行脚本。
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|s| s.as_str()) {
+        None => {
+            repl();
+            ExitCode::SUCCESS
+        }
+        Some("run") => {
+            let keep_going = args.iter().any(|a| a == "--keep-going");
+            let files: Vec<&String> = args[1..].iter().filter(|a| !a.starts_with("--")).collect();
+            match files[..] {
+                [file] => run(file, keep_going),
+                _ => {
+                    eprintln!("usage: calculus run <file> [--keep-going]");
+                    ExitCode::from(2)
+                }
+            }
+        }
+        Some(other) => {
+            eprintln!("unknown argument: {}", other);
+            eprintln!("usage: calculus [run <file> [--keep-going]]");
+            ExitCode::from(2)
         }
     }
 }
-//cargo build --target x86_64-pc-windows-gnu --release 
\ No newline at end of file
+
+/// 执行脚本，任何一行失败时返回非零退出码。
+fn run(file: &str, keep_going: bool) -> ExitCode {
+    let script = match std::fs::read_to_string(file) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("error: cannot read {}: {}", file, e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut session = Session::new();
+    let errors = session.run_script(&script, keep_going, |text| println!("{}", text));
+    for error in &errors {
+        eprintln!("{}", error.render(file));
+    }
+
+    if errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn repl() {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("error: cannot start line editor: {}", e);
+            return;
+        }
+    };
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // 第一次运行时历史文件还不存在
+        let _ = editor.load_history(path);
+    }
+
+    let mut session = Session::new();
+    loop {
+        match editor.readline("> ") {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    let _ = editor.add_history_entry(line.as_str());
+                }
+                match session.execute(&line) {
+                    Ok(Outcome::Output(text)) => {
+                        if !text.is_empty() {
+                            println!("{}", text);
+                        }
+                    }
+                    Ok(Outcome::Quit) => break,
+                    Err(e) => println!("{}", render_error(&e, &line)),
+                }
+            }
+            // Ctrl-C 只放弃当前输入的一行，Ctrl-D 退出
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                break;
+            }
+        }
+    }
+
+    if let Some(path) = &history
+        && let Err(e) = editor.save_history(path)
+    {
+        eprintln!("error: cannot save history: {}", e);
+    }
+}
+//cargo build --target x86_64-pc-windows-gnu --release
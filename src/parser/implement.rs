@@ -0,0 +1,292 @@
+use crate::{
+    error::CalcError,
+    expr::{Expr, Span, SpannedToken, Token},
+    parser::Parser,
+    tokenlizer::Tokenlizer,
+};
+
+impl Parser {
+    pub fn new(tokens: &[SpannedToken]) -> Parser {
+        Parser {
+            tokens: tokens.to_vec(),
+            i: 0,
+            calls: Vec::new(),
+        }
+    }
+
+    /// 当前 token 的位置；若已读完全部 token，则指向输入末尾之后的一格。
+    pub fn span(&self) -> Span {
+        if let Some(t) = self.tokens.get(self.i) {
+            t.span
+        } else if let Some(t) = self.tokens.last() {
+            Span::new(t.span.end, t.span.end + 1)
+        } else {
+            Span::default()
+        }
+    }
+
+    /// 生成指向当前 token 的错误。
+    pub fn error(&self, message: impl Into<String>) -> CalcError {
+        CalcError::Parse {
+            message: message.into(),
+            span: self.span(),
+        }
+    }
+
+    /// 返回当前 token，读完时报 "unexpected end of input"。
+    fn peek(&self) -> Result<Token, CalcError> {
+        match self.tokens.get(self.i) {
+            Some(t) => Ok(t.token.clone()),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    pub fn expect(&mut self, token: Token) -> Result<(), CalcError> {
+        if self.i >= self.tokens.len() {
+            return Err(self.error(format!("expect {} but find end of input", token)));
+        }
+
+        if self.tokens[self.i].token == token {
+            self.i += 1;
+            return Ok(());
+        }
+
+        let mut err = "expect ".to_string();
+        err.push_str(&token.to_string());
+        err.push_str(" but find ");
+        err.push_str(&self.tokens[self.i].token.to_string());
+
+        Err(self.error(err))
+    }
+
+    pub fn is(&mut self, token: Token) -> Result<bool, CalcError> {
+        if self.i >= self.tokens.len() {
+            return Ok(false);
+        }
+
+        if self.tokens[self.i].token == token {
+            self.i += 1;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// 要求 token 已全部读完。
+    pub fn expect_end(&self) -> Result<(), CalcError> {
+        if let Some(t) = self.tokens.get(self.i) {
+            return Err(self.error(format!("unexpected {}", t.token)));
+        }
+        Ok(())
+    }
+
+    /// 已解析部分中出现过的函数调用，供调用方自行检查函数是否存在。
+    pub fn calls(&self) -> &[(String, usize, Span)] {
+        &self.calls
+    }
+
+    /// 解析函数签名 `name(arg, ...)`，返回 `Expr::Func(name, [Var(arg), ...])`。
+    pub fn parse_signature(&mut self) -> Result<Expr, CalcError> {
+        let name = match self.peek()? {
+            Token::Identifier(name) => name,
+            other => return Err(self.error(format!("expect function name but find {}", other))),
+        };
+        self.i += 1;
+
+        self.expect(Token::Operator('('))?;
+        let mut args = Vec::<Expr>::new();
+
+        // 参数之间必须用 ',' 分隔
+        if !self.is(Token::Operator(')'))? {
+            loop {
+                match self.peek()? {
+                    Token::Identifier(arg) => args.push(Expr::Var(arg)),
+                    other => {
+                        return Err(self.error(format!("expect parameter name but find {}", other)));
+                    }
+                }
+                self.i += 1;
+
+                if !self.is(Token::Operator(','))? {
+                    break;
+                }
+            }
+            self.expect(Token::Operator(')'))?;
+        }
+
+        #[cfg(feature = "trace")]
+        {
+            eprintln!("args.len()={}", args.len());
+        }
+
+        Ok(Expr::Func(name, args))
+    }
+
+    /// 解析一个完整的表达式，要求之后没有多余的 token。
+    pub fn parse(&mut self) -> Result<Expr, CalcError> {
+        let res = self.parse_add_or_sub()?;
+        self.expect_end()?;
+        Ok(res)
+    }
+
+    fn parse_call(&mut self, name: &str, name_span: Span) -> Result<Expr, CalcError> {
+        let mut args = Vec::<Expr>::new();
+
+        // 实参之间必须用 ',' 分隔，因此 f(x,-y) 中的 '-' 是负号而不是减号
+        if !self.is(Token::Operator(')'))? {
+            loop {
+                args.push(self.parse_add_or_sub()?);
+                if !self.is(Token::Operator(','))? {
+                    break;
+                }
+            }
+            self.expect(Token::Operator(')'))?;
+        }
+
+        self.calls.push((name.to_string(), args.len(), name_span));
+
+        Ok(Expr::Func(name.to_string(), args))
+    }
+
+    pub fn parse_add_or_sub(&mut self) -> Result<Expr, CalcError> {
+        let mut left = self.parse_mul_or_div()?;
+
+        loop {
+            if self.is(Token::Operator('+'))? {
+                #[cfg(feature = "trace")]
+                {
+                    eprintln!("+");
+                }
+                let right = self.parse_mul_or_div()?;
+                left = Expr::Add(Box::new(left), Box::new(right));
+            } else if self.is(Token::Operator('-'))? {
+                #[cfg(feature = "trace")]
+                {
+                    eprintln!("-");
+                }
+                let right = self.parse_mul_or_div()?;
+                left = Expr::Sub(Box::new(left), Box::new(right));
+            } else {
+                break;
+            }
+        }
+
+        Ok(left)
+    }
+
+    fn parse_mul_or_div(&mut self) -> Result<Expr, CalcError> {
+        let mut left = self.parse_unary()?;
+
+        loop {
+            if self.is(Token::Operator('*'))? {
+                #[cfg(feature = "trace")]
+                {
+                    eprintln!("*");
+                }
+                let right = self.parse_unary()?;
+                left = Expr::Mul(Box::<Expr>::new(left), Box::<Expr>::new(right));
+            } else if self.is(Token::Operator('/'))? {
+                #[cfg(feature = "trace")]
+                {
+                    eprintln!("/");
+                }
+                let right = self.parse_unary()?;
+                left = Expr::Div(Box::<Expr>::new(left), Box::<Expr>::new(right));
+            } else {
+                #[cfg(feature = "trace")]
+                {
+                    if self.i < self.tokens.len() {
+                        eprintln!("{}", self.tokens[self.i].token);
+                    }
+                }
+                break;
+            }
+        }
+
+        Ok(left)
+    }
+
+    /// 前缀的 '+' / '-'，优先级介于乘除与乘方之间。
+    fn parse_unary(&mut self) -> Result<Expr, CalcError> {
+        if self.is(Token::Operator('-'))? {
+            let operand = self.parse_unary()?;
+            Ok(Expr::Neg(Box::new(operand)))
+        } else if self.is(Token::Operator('+'))? {
+            self.parse_unary()
+        } else {
+            self.parse_power()
+        }
+    }
+
+    /// '^' 是右结合的：2^3^2 即 2^(3^2)；指数部分可以带一元运算符，如 x^-1。
+    fn parse_power(&mut self) -> Result<Expr, CalcError> {
+        let mut left = self.parse_primary()?;
+
+        if self.is(Token::Operator('^'))? {
+            // parse_unary 会再回到 parse_power，从而实现右结合的连续乘方
+            let right = self.parse_unary()?;
+            left = Expr::Power(Box::<Expr>::new(left), Box::<Expr>::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, CalcError> {
+        let peek = self.peek()?;
+        let res;
+
+        #[cfg(feature = "trace")]
+        {
+            eprintln!("peek={}", peek);
+        }
+
+        if self.is(Token::Operator('('))? {
+            res = self.parse_add_or_sub()?;
+            self.expect(Token::Operator(')'))?;
+        } else if let Token::Identifier(_) = peek {
+            let name_span = self.span();
+            self.i += 1;
+
+            if self.is(Token::Operator('('))? {
+                res = self.parse_call(&peek.as_identifier()?, name_span)?;
+            } else {
+                res = Expr::Var(peek.as_identifier()?);
+            }
+        } else if let Token::Const(_) = peek {
+            self.i += 1;
+            res = Expr::Const(peek.as_const()?);
+        } else if let Token::Log() = peek {
+            self.i += 1;
+            self.expect(Token::Operator('('))?;
+
+            let l = self.parse_add_or_sub()?;
+            self.expect(Token::Operator(','))?;
+            let r = self.parse_add_or_sub()?;
+
+            self.expect(Token::Operator(')'))?;
+
+            res = Expr::Log(Box::<Expr>::new(l), Box::<Expr>::new(r));
+        } else if let Token::Trifuncs(ref name) = peek {
+            self.i += 1;
+            self.expect(Token::Operator('('))?;
+
+            let var = self.parse_add_or_sub()?;
+
+            self.expect(Token::Operator(')'))?;
+
+            res = Expr::Trifuncs(name.clone(), Box::<Expr>::new(var));
+        } else {
+            return Err(self.error(format!("unexpected {}", peek)));
+        }
+
+        Ok(res)
+    }
+}
+
+impl Expr {
+    /// 把任意表达式文本解析为 `Expr`，不会查询或修改任何 `FunctionTable`。
+    pub fn parse(text: &str) -> Result<Expr, CalcError> {
+        let tokens = Tokenlizer::new(text).tokenlize()?;
+        Parser::new(&tokens).parse()
+    }
+}
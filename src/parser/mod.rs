@@ -0,0 +1,12 @@
+use crate::expr::{Span, SpannedToken};
+
+pub mod implement;
+
+/// 递归下降的表达式解析器，只依赖 token 序列，不查询也不修改 `FunctionTable`。
+pub struct Parser {
+    tokens: Vec<SpannedToken>,
+    i: usize,
+
+    /// 解析过程中遇到的函数调用：(函数名, 参数个数, 函数名所在位置)
+    calls: Vec<(String, usize, Span)>,
+}
@@ -0,0 +1,459 @@
+use crate::{
+    expr::{Expr, implicit_mul, is_half},
+    function::Function,
+};
+
+/// 输出表达式和函数的方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Style {
+    /// `Display` 的单行纯文本，可以再次解析
+    #[default]
+    Plain,
+    /// 单行 Unicode，如 `3ˣ·ln(3)`、`√(x + 1)`、`x²`
+    Unicode,
+    /// 多行排版，分式上下叠放，指数写在右上方
+    TwoD,
+    /// LaTeX 公式
+    Latex,
+}
+
+const SUPERSCRIPT: [char; 10] = ['⁰', '¹', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+const SUBSCRIPT: [char; 10] = ['₀', '₁', '₂', '₃', '₄', '₅', '₆', '₇', '₈', '₉'];
+
+/// 整数常数的上标或下标形式，例如 -12 得到 `⁻¹²`；不是整数时返回 None。
+fn script_digits(expr: &Expr, digits: &[char; 10], minus: char) -> Option<String> {
+    let c = match expr {
+        Expr::Const(c) => *c,
+        Expr::Neg(x) => match x.as_ref() {
+            Expr::Const(c) => -c,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    if c.fract() != 0.0 || c.abs() > 1e15 {
+        return None;
+    }
+
+    let mut res = String::new();
+    if c < 0.0 {
+        res.push(minus);
+    }
+    for d in (c.abs() as u64).to_string().chars() {
+        res.push(digits[d.to_digit(10)? as usize]);
+    }
+    Some(res)
+}
+
+/// 常数的 Unicode 形式，π、e 和 ∞ 写成符号，负号用 `−`。
+fn unicode_const(x: f64) -> String {
+    if x == std::f64::consts::PI {
+        "π".to_string()
+    } else if x == std::f64::consts::E {
+        "e".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "∞" } else { "−∞" }.to_string()
+    } else {
+        x.to_string().replace('-', "−")
+    }
+}
+
+/// 底数是否可以不加括号直接写在 `√` 后面。
+fn is_atom(expr: &Expr) -> bool {
+    match expr {
+        Expr::Const(c) => !c.is_sign_negative(),
+        Expr::Var(_) | Expr::Func(_, _) | Expr::Trifuncs(_, _) | Expr::Log(_, _) => true,
+        _ => false,
+    }
+}
+
+/// 二维排版中的一块文字，各行等宽，`baseline` 是与左右相邻内容对齐的行。
+#[derive(Debug, Clone)]
+struct Block {
+    lines: Vec<String>,
+    baseline: usize,
+}
+
+impl Block {
+    fn atom(text: &str) -> Block {
+        Block {
+            lines: vec![text.to_string()],
+            baseline: 0,
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.lines
+            .iter()
+            .map(|l| l.chars().count())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn height(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// 把各行补齐到同一宽度。
+    fn padded(mut self) -> Block {
+        let width = self.width();
+        for line in &mut self.lines {
+            let n = line.chars().count();
+            line.push_str(&" ".repeat(width - n));
+        }
+        self
+    }
+
+    /// 左右拼接，按基线对齐。
+    fn beside(self, other: Block) -> Block {
+        let (left, right) = (self.padded(), other.padded());
+        let above = left.baseline.max(right.baseline);
+        let below = (left.height() - left.baseline).max(right.height() - right.baseline);
+
+        let row = |block: &Block, r: usize| {
+            (r + block.baseline)
+                .checked_sub(above)
+                .and_then(|i| block.lines.get(i).cloned())
+                .unwrap_or_else(|| " ".repeat(block.width()))
+        };
+        let lines = (0..above + below)
+            .map(|r| row(&left, r) + &row(&right, r))
+            .collect();
+
+        Block {
+            lines,
+            baseline: above,
+        }
+    }
+
+    /// 分式：分子、分数线、分母上下叠放，基线在分数线上。
+    fn fraction(num: Block, den: Block) -> Block {
+        let width = num.width().max(den.width()) + 2;
+        let center = |block: Block| {
+            let pad = (width - block.width()) / 2;
+            block
+                .padded()
+                .lines
+                .into_iter()
+                .map(move |l| format!("{}{}", " ".repeat(pad), l))
+        };
+
+        let baseline = num.height();
+        let mut lines: Vec<String> = center(num).collect();
+        lines.push("─".repeat(width));
+        lines.extend(center(den));
+
+        Block { lines, baseline }.padded()
+    }
+
+    /// 在右上方写上指数。
+    fn raise(self, exp: Block) -> Block {
+        let (base, exp) = (self.padded(), exp.padded());
+        let (bw, ew) = (base.width(), exp.width());
+
+        let mut lines: Vec<String> = exp
+            .lines
+            .iter()
+            .map(|l| format!("{}{}", " ".repeat(bw), l))
+            .collect();
+        lines.extend(
+            base.lines
+                .iter()
+                .map(|l| format!("{}{}", l, " ".repeat(ew))),
+        );
+
+        Block {
+            baseline: exp.height() + base.baseline,
+            lines,
+        }
+    }
+
+    /// 在右下方写上下标，用于对数的底数。
+    fn lower(self, sub: Block) -> Block {
+        let (base, sub) = (self.padded(), sub.padded());
+        let (bw, sw) = (base.width(), sub.width());
+
+        let mut lines: Vec<String> = base
+            .lines
+            .iter()
+            .map(|l| format!("{}{}", l, " ".repeat(sw)))
+            .collect();
+        lines.extend(sub.lines.iter().map(|l| format!("{}{}", " ".repeat(bw), l)));
+
+        Block {
+            baseline: base.baseline,
+            lines,
+        }
+    }
+
+    /// 加括号，多行内容使用可伸缩的括号。
+    fn parens(self) -> Block {
+        let block = self.padded();
+        let h = block.height();
+        let lines = block
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(i, l)| match (h, i) {
+                (1, _) => format!("({})", l),
+                (_, 0) => format!("⎛{}⎞", l),
+                (_, i) if i + 1 == h => format!("⎝{}⎠", l),
+                _ => format!("⎜{}⎟", l),
+            })
+            .collect();
+
+        Block {
+            lines,
+            baseline: block.baseline,
+        }
+    }
+
+    /// 根号：上方一条横线，左侧是 `√`。
+    fn sqrt(self) -> Block {
+        let block = self.padded();
+        let h = block.height();
+
+        let mut lines = vec![format!(" {}", "_".repeat(block.width()))];
+        for (i, l) in block.lines.iter().enumerate() {
+            let mark = if i + 1 == h { '√' } else { '│' };
+            lines.push(format!("{}{}", mark, l));
+        }
+
+        Block {
+            lines,
+            baseline: block.baseline + 1,
+        }
+    }
+
+    fn render(self) -> String {
+        let lines: Vec<&str> = self.lines.iter().map(|l| l.trim_end()).collect();
+        lines.join("\n")
+    }
+}
+
+impl Expr {
+    /// 按 `style` 输出表达式。
+    pub fn render(&self, style: Style) -> String {
+        match style {
+            Style::Plain => self.to_string(),
+            Style::Unicode => self.to_unicode(),
+            Style::TwoD => self.layout(0).render(),
+            Style::Latex => self.to_latex(),
+        }
+    }
+
+    /// 单行 Unicode 输出，例如 `x² + 2x·sin(x)`。
+    pub fn to_unicode(&self) -> String {
+        let mut out = String::new();
+        self.unicode_with_prec(&mut out, 0);
+        out
+    }
+
+    /// 与 `fmt_with_prec` 相同的优先级规则。
+    fn unicode_with_prec(&self, out: &mut String, parent_prec: u8) {
+        let my_prec = match self {
+            Expr::Power(_, y) if is_half(y) => 5,
+            _ => self.precedence(),
+        };
+        let need_paren = my_prec < parent_prec;
+
+        if need_paren {
+            out.push('(');
+        }
+
+        match self {
+            Expr::Const(x) => out.push_str(&unicode_const(*x)),
+            Expr::Var(x) => out.push_str(x),
+            Expr::Add(x, y) => {
+                x.unicode_with_prec(out, my_prec);
+                match y.as_ref() {
+                    Expr::Neg(z) => {
+                        out.push_str(" − ");
+                        z.unicode_with_prec(out, my_prec + 1);
+                    }
+                    Expr::Const(c) if c.is_sign_negative() => {
+                        out.push_str(" − ");
+                        out.push_str(&unicode_const(-c));
+                    }
+                    _ => {
+                        out.push_str(" + ");
+                        y.unicode_with_prec(out, my_prec + 1);
+                    }
+                }
+            }
+            Expr::Sub(x, y) => {
+                x.unicode_with_prec(out, my_prec);
+                out.push_str(" − ");
+                y.unicode_with_prec(out, my_prec + 1);
+            }
+            Expr::Mul(x, y) => {
+                x.unicode_with_prec(out, my_prec);
+                if !implicit_mul(x, y) {
+                    out.push('·');
+                }
+                y.unicode_with_prec(out, my_prec + 1);
+            }
+            Expr::Div(x, y) => {
+                x.unicode_with_prec(out, my_prec);
+                out.push('/');
+                y.unicode_with_prec(out, my_prec + 1);
+            }
+            Expr::Power(x, y) if is_half(y) => {
+                out.push('√');
+                x.unicode_with_prec(out, if is_atom(x) { 0 } else { 6 });
+            }
+            Expr::Power(x, y) => match (x.as_ref(), script_digits(y, &SUPERSCRIPT, '⁻')) {
+                // 三角函数的正整数次幂写成 sin²(x)
+                (Expr::Trifuncs(name, pvar), Some(sup)) if !sup.starts_with('⁻') => {
+                    out.push_str(&format!("{}{}(", name, sup));
+                    pvar.unicode_with_prec(out, 0);
+                    out.push(')');
+                }
+                (_, Some(sup)) => {
+                    x.unicode_with_prec(out, my_prec + 1);
+                    out.push_str(&sup);
+                }
+                (_, None) => {
+                    x.unicode_with_prec(out, my_prec + 1);
+                    out.push('^');
+                    y.unicode_with_prec(out, my_prec - 1);
+                }
+            },
+            Expr::Neg(x) => {
+                out.push('−');
+                x.unicode_with_prec(out, my_prec);
+            }
+            Expr::Log(x, y) => {
+                if **x == Expr::Const(std::f64::consts::E) {
+                    out.push_str("ln(");
+                } else if let Some(sub) = script_digits(x, &SUBSCRIPT, '₋') {
+                    out.push_str(&format!("log{}(", sub));
+                } else {
+                    out.push_str("log(");
+                    x.unicode_with_prec(out, 0);
+                    out.push_str(", ");
+                }
+                y.unicode_with_prec(out, 0);
+                out.push(')');
+            }
+            Expr::Trifuncs(name, pvar) => {
+                out.push_str(&format!("{}(", name));
+                pvar.unicode_with_prec(out, 0);
+                out.push(')');
+            }
+            Expr::Func(name, args) => {
+                out.push_str(&format!("{}(", name));
+                for (i, arg) in args.iter().enumerate() {
+                    arg.unicode_with_prec(out, 0);
+                    if i + 1 != args.len() {
+                        out.push_str(", ");
+                    }
+                }
+                out.push(')');
+            }
+            Expr::Equal(x, y) => {
+                x.unicode_with_prec(out, my_prec);
+                out.push_str(" = ");
+                y.unicode_with_prec(out, my_prec);
+            }
+        }
+
+        if need_paren {
+            out.push(')');
+        }
+    }
+
+    /// 二维排版，分式和根式自带分组。
+    fn layout(&self, parent_prec: u8) -> Block {
+        let my_prec = match self {
+            Expr::Div(_, _) => 5,
+            Expr::Power(_, y) if is_half(y) => 5,
+            _ => self.precedence(),
+        };
+
+        let block = match self {
+            Expr::Const(x) => Block::atom(&unicode_const(*x)),
+            Expr::Var(x) => Block::atom(x),
+            Expr::Add(x, y) => {
+                let left = x.layout(my_prec);
+                match y.as_ref() {
+                    Expr::Neg(z) => left
+                        .beside(Block::atom(" − "))
+                        .beside(z.layout(my_prec + 1)),
+                    Expr::Const(c) if c.is_sign_negative() => left
+                        .beside(Block::atom(" − "))
+                        .beside(Block::atom(&unicode_const(-c))),
+                    _ => left
+                        .beside(Block::atom(" + "))
+                        .beside(y.layout(my_prec + 1)),
+                }
+            }
+            Expr::Sub(x, y) => x
+                .layout(my_prec)
+                .beside(Block::atom(" − "))
+                .beside(y.layout(my_prec + 1)),
+            Expr::Mul(x, y) => {
+                let op = if implicit_mul(x, y) { "" } else { "·" };
+                x.layout(my_prec)
+                    .beside(Block::atom(op))
+                    .beside(y.layout(my_prec + 1))
+            }
+            Expr::Div(x, y) => Block::fraction(x.layout(0), y.layout(0)),
+            Expr::Power(x, y) if is_half(y) => x.layout(0).sqrt(),
+            Expr::Power(x, y) => {
+                // 分式作底数时同样需要括号
+                let base = match x.as_ref() {
+                    Expr::Div(_, _) => x.layout(0).parens(),
+                    _ => x.layout(my_prec + 1),
+                };
+                base.raise(y.layout(0))
+            }
+            Expr::Neg(x) => Block::atom("−").beside(x.layout(my_prec)),
+            Expr::Log(x, y) => {
+                let name = if **x == Expr::Const(std::f64::consts::E) {
+                    Block::atom("ln")
+                } else {
+                    Block::atom("log").lower(x.layout(0))
+                };
+                name.beside(y.layout(0).parens())
+            }
+            Expr::Trifuncs(name, pvar) => Block::atom(name).beside(pvar.layout(0).parens()),
+            Expr::Func(name, args) => {
+                let mut inner = Block::atom("");
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        inner = inner.beside(Block::atom(", "));
+                    }
+                    inner = inner.beside(arg.layout(0));
+                }
+                Block::atom(name).beside(inner.parens())
+            }
+            Expr::Equal(x, y) => x
+                .layout(my_prec)
+                .beside(Block::atom(" = "))
+                .beside(y.layout(my_prec)),
+        };
+
+        if my_prec < parent_prec {
+            block.parens()
+        } else {
+            block
+        }
+    }
+}
+
+impl Function {
+    /// 按 `style` 输出函数定义。
+    pub fn render(&self, style: Style) -> String {
+        match style {
+            Style::Plain => self.to_string(),
+            Style::Unicode => format!("{} = {}", self.symble.to_unicode(), self.body.to_unicode()),
+            Style::TwoD => self
+                .symble
+                .layout(0)
+                .beside(Block::atom(" = "))
+                .beside(self.body.layout(0))
+                .render(),
+            Style::Latex => self.to_latex(),
+        }
+    }
+}
@@ -0,0 +1,582 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    error::CalcError,
+    expr::Expr,
+    function::{
+        DefineMode, DerivativeMode, EvalMode, Function, FunctionTable,
+        batch::BatchMode,
+        cubature::{CubatureMethod, Limits},
+        quadrature::QuadratureOptions,
+    },
+    parser::Parser,
+    pretty::Style,
+    repl::{HELP, Outcome, Session},
+    tokenlizer::Tokenlizer,
+};
+
+/// `table` 命令最多输出的行数，避免一行输入耗尽内存。
+const MAX_TABLE_ROWS: usize = 1_000_000;
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::with_table(Rc::new(RefCell::new(FunctionTable::new())))
+    }
+
+    /// 在已有的函数表上开始会话。
+    pub fn with_table(function_table: Rc<RefCell<FunctionTable>>) -> Session {
+        Session {
+            function_table,
+            style: Style::Plain,
+        }
+    }
+
+    pub fn function_table(&self) -> Rc<RefCell<FunctionTable>> {
+        self.function_table.clone()
+    }
+
+    pub fn style(&self) -> Style {
+        self.style
+    }
+
+    /// 设置输出函数时使用的格式。
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    /// 执行一行命令，空行和 `#` 开头的注释什么也不做。
+    ///
+    /// 错误中的位置以整行为准，可以直接用 `render_error(&err, line)` 输出。
+    pub fn execute(&mut self, line: &str) -> Result<Outcome, CalcError> {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            return Ok(Outcome::Output(String::new()));
+        }
+
+        let (command, payload) = trimmed
+            .split_once(char::is_whitespace)
+            .unwrap_or((trimmed, ""));
+        // 参数部分在整行中的字符偏移，用于修正解析错误的位置
+        let offset = line.chars().count() - payload.trim_start().chars().count();
+        let payload = payload.trim();
+
+        let output = match command {
+            "def" => self.define(payload, offset, DefineMode::Strict)?,
+            "redef" => self.define(payload, offset, DefineMode::Replace)?,
+            "del" => self.delete(payload)?,
+            "eval" => self.evaluate(payload, offset, EvalMode::Lenient)?,
+            "check" => self.evaluate(payload, offset, EvalMode::Checked)?,
+            "diff" => self.derivative(payload)?,
+            "int" => self.integral(payload)?,
+            "partial" => self.partial(payload)?,
+            "grad" => self.gradient(payload)?,
+            "hess" => self.hessian(payload)?,
+            "jac" => self.jacobian(payload)?,
+            "quad" => self.quad(payload)?,
+            "multiquad" => self.multiquad(payload, offset)?,
+            "table" => self.table(payload)?,
+            "save" => self.save(payload)?,
+            "load" => self.load(payload)?,
+            "latex" => self.resolve(payload)?.to_latex(),
+            "list" => self.list(),
+            "show" => self.show(payload)?,
+            "set" => self.set(payload)?,
+            "help" => HELP.to_string(),
+            "quit" | "exit" => return Ok(Outcome::Quit),
+            _ => {
+                return Err(CalcError::InvalidArgument(format!(
+                    "unknown command: {}, type help for a list of commands",
+                    command
+                )));
+            }
+        };
+
+        Ok(Outcome::Output(output))
+    }
+
+    /// 按 `f` 或 `f(x,y)` 查找函数；只写函数名时该名字只能有一个重载。
+    fn resolve(&self, target: &str) -> Result<Function, CalcError> {
+        let (name, args) = split_call(target);
+        let table = self.function_table.borrow();
+
+        if target.contains('(') {
+            return table
+                .find(&name, args.len())
+                .cloned()
+                .ok_or(CalcError::UnknownFunction {
+                    name,
+                    arity: args.len(),
+                    span: None,
+                });
+        }
+
+        let overloads: Vec<&Function> = table.iter().filter(|f| f.name() == name).collect();
+        match overloads[..] {
+            [] => Err(CalcError::InvalidArgument(format!(
+                "no function named {}",
+                name
+            ))),
+            [func] => Ok(func.clone()),
+            _ => Err(CalcError::InvalidArgument(format!(
+                "{} is overloaded, write its arguments to pick one, e.g. {}(x)",
+                name, name
+            ))),
+        }
+    }
+
+    fn define(&mut self, text: &str, offset: usize, mode: DefineMode) -> Result<String, CalcError> {
+        let tokens = Tokenlizer::new(text)
+            .tokenlize()
+            .map_err(|e| e.shift(offset))?;
+
+        let func = Function::new_with_mode(&tokens, self.function_table.clone(), mode)
+            .map_err(|e| e.shift(offset))?;
+
+        let (name, argc) = func.key();
+        let mut lines = vec![func.render(self.style)];
+        for (dep, dep_argc) in self.function_table.borrow().dependents(&name, argc) {
+            lines.push(format!(
+                "note: {}/{} depends on {}/{}",
+                dep, dep_argc, name, argc
+            ));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn delete(&mut self, target: &str) -> Result<String, CalcError> {
+        let (name, argc) = self.resolve(target)?.key();
+        let func = self.function_table.borrow_mut().delete(&name, argc)?;
+        Ok(format!("deleted {}", func.render(self.style)))
+    }
+
+    /// 把整个表达式当作没有参数的函数求值，例如 `f(2)+g(1,3)`。
+    fn evaluate(&self, text: &str, offset: usize, mode: EvalMode) -> Result<String, CalcError> {
+        let tokens = Tokenlizer::new(text)
+            .tokenlize()
+            .map_err(|e| e.shift(offset))?;
+        let mut parser = Parser::new(&tokens);
+        let body = parser.parse().map_err(|e| e.shift(offset))?;
+
+        // 先检查调用的函数，未定义时能指出位置
+        for (name, argc, span) in parser.calls() {
+            if !self.function_table.borrow().contains(name, *argc) {
+                let err = CalcError::UnknownFunction {
+                    name: name.clone(),
+                    arity: *argc,
+                    span: Some(*span),
+                };
+                return Err(err.shift(offset));
+            }
+        }
+
+        let func = Function {
+            symble: Expr::Func(String::new(), Vec::new()),
+            body,
+        };
+
+        let ans = func.caculate_with_mode(&[], self.function_table.clone(), mode)?;
+        Ok(format!("{}={}", text, ans))
+    }
+
+    /// `diff f x`，跟上 `symbolic` 时保留 f_x(g(x)) 形式的引用。
+    fn derivative(&mut self, text: &str) -> Result<String, CalcError> {
+        let (target, rest) = split_target(text);
+        let func = self.resolve(target)?;
+
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        let (dx, mode) = match words[..] {
+            [dx] => (dx, DerivativeMode::Inline),
+            [dx, "symbolic"] => (dx, DerivativeMode::Symbolic),
+            _ => return Err(usage("diff <function> <var> [symbolic]")),
+        };
+
+        let df = func.derivative_with_mode(&dx.to_string(), self.function_table.clone(), mode)?;
+        self.function_table
+            .borrow_mut()
+            .insert_derived(df.clone(), func.key())?;
+        Ok(df.render(self.style))
+    }
+
+    fn integral(&mut self, text: &str) -> Result<String, CalcError> {
+        let (target, rest) = split_target(text);
+        let func = self.resolve(target)?;
+
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        let [dx] = words[..] else {
+            return Err(usage("int <function> <var>"));
+        };
+
+        let f = func.integral(&dx.to_string(), self.function_table.clone())?;
+        self.function_table
+            .borrow_mut()
+            .insert_derived(f.clone(), func.key())?;
+        Ok(f.render(self.style))
+    }
+
+    /// `partial f x x y`：依次对 x、x、y 求偏导。
+    fn partial(&mut self, text: &str) -> Result<String, CalcError> {
+        let (target, rest) = split_target(text);
+        let func = self.resolve(target)?;
+
+        let vars: Vec<String> = rest.split_whitespace().map(|s| s.to_string()).collect();
+        if vars.is_empty() {
+            return Err(usage("partial <function> <var> <var> ..."));
+        }
+
+        Ok(func
+            .partial_derivative(&vars, self.function_table.clone())?
+            .render(self.style))
+    }
+
+    /// `grad f` 输出符号形式，`grad f(1,2)` 输出该点处的值。
+    fn gradient(&self, target: &str) -> Result<String, CalcError> {
+        let func = self.resolve(target)?;
+        let grad = func.gradient(self.function_table.clone())?;
+
+        let (_, args) = split_call(target);
+        Ok(match numeric_args(&args) {
+            Some(point) if !args.is_empty() => {
+                format!("{:?}", grad.caculate(&point, self.function_table.clone())?)
+            }
+            _ => grad.to_string(),
+        })
+    }
+
+    /// Hessian 矩阵，参数格式与 `grad` 相同。
+    fn hessian(&self, target: &str) -> Result<String, CalcError> {
+        let func = self.resolve(target)?;
+        let hess = func.hessian(self.function_table.clone())?;
+
+        let (_, args) = split_call(target);
+        Ok(match numeric_args(&args) {
+            Some(point) if !args.is_empty() => {
+                let rows = hess.caculate(&point, self.function_table.clone())?;
+                let rows: Vec<String> = rows.iter().map(|row| format!("{:?}", row)).collect();
+                rows.join("\n")
+            }
+            _ => hess.to_string(),
+        })
+    }
+
+    /// `jac f g (x,y)` 输出符号形式，`jac f g (1,2)` 输出该点处的值。
+    fn jacobian(&self, text: &str) -> Result<String, CalcError> {
+        let Some(open) = text.find('(') else {
+            return Err(usage("jac <function> <function> ... (<args>)"));
+        };
+        let (_, args) = split_call(&text[open..]);
+
+        let mut funcs = Vec::new();
+        for name in text[..open].split_whitespace() {
+            match self.function_table.borrow().find(name, args.len()) {
+                Some(func) => funcs.push(func.clone()),
+                None => {
+                    return Err(CalcError::UnknownFunction {
+                        name: name.to_string(),
+                        arity: args.len(),
+                        span: None,
+                    });
+                }
+            }
+        }
+
+        let jac = Function::jacobian(&funcs, self.function_table.clone())?;
+        Ok(match numeric_args(&args) {
+            Some(point) => {
+                let rows = jac.caculate(&point, self.function_table.clone())?;
+                let rows: Vec<String> = rows.iter().map(|row| format!("{:?}", row)).collect();
+                rows.join("\n")
+            }
+            None => jac.to_string(),
+        })
+    }
+
+    /// `quad f(x,2) x 0 inf`：积分变量保留为名字，其余参数给出数值。
+    fn quad(&self, text: &str) -> Result<String, CalcError> {
+        let (target, rest) = split_target(text);
+        let func = self.resolve(target)?;
+        let args = call_args(target, &func);
+
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        let [dx, a, b] = words[..] else {
+            return Err(usage("quad <function> <var> <lower> <upper>"));
+        };
+        let (Ok(a), Ok(b)) = (a.parse::<f64>(), b.parse::<f64>()) else {
+            return Err(CalcError::InvalidArgument(
+                "bounds must be numbers or inf".to_string(),
+            ));
+        };
+
+        let fixed = fixed_args(&args, |arg| arg == dx)?;
+        let res = func.definite_integral(
+            &dx.to_string(),
+            a,
+            b,
+            &fixed,
+            &QuadratureOptions::default(),
+            self.function_table.clone(),
+        )?;
+        Ok(res.to_string())
+    }
+
+    /// `multiquad f(x,y,2) x 0 1; y 0 x`，积分变量从外到内排列，
+    /// 最后可以跟 `; mc <样本数> <种子>` 或 `; sobol <样本数>` 选择计算方式。
+    fn multiquad(&self, text: &str, offset: usize) -> Result<String, CalcError> {
+        let (target, rest) = split_target(text);
+        let func = self.resolve(target)?;
+        let args = call_args(target, &func);
+
+        let mut limits = Vec::new();
+        let mut method = CubatureMethod::Adaptive;
+        for part in rest.split(';') {
+            let words: Vec<&str> = part.split_whitespace().collect();
+            match words[..] {
+                ["adaptive"] => method = CubatureMethod::Adaptive,
+                ["mc", samples, seed] => match (samples.parse(), seed.parse()) {
+                    (Ok(samples), Ok(seed)) => {
+                        method = CubatureMethod::MonteCarlo { samples, seed }
+                    }
+                    _ => return Err(usage("mc <samples> <seed>")),
+                },
+                ["sobol", samples] => match samples.parse() {
+                    Ok(samples) => method = CubatureMethod::QuasiMonteCarlo { samples },
+                    _ => return Err(usage("sobol <samples>")),
+                },
+                [var, lower, upper] => {
+                    let lower = parse_bound(lower, offset + char_offset(text, lower))?;
+                    let upper = parse_bound(upper, offset + char_offset(text, upper))?;
+                    limits.push(Limits::new(var, lower, upper))
+                }
+                _ => {
+                    return Err(usage(
+                        "multiquad <function> <var> <lower> <upper>; ... [; mc <samples> <seed> | ; sobol <samples>]",
+                    ));
+                }
+            }
+        }
+
+        let fixed = fixed_args(&args, |arg| limits.iter().any(|l| l.var == arg))?;
+        let res = func.multiple_integral(
+            &limits,
+            &fixed,
+            method,
+            &QuadratureOptions::default(),
+            self.function_table.clone(),
+        )?;
+        Ok(res.to_string())
+    }
+
+    /// `table f(x,2) x 0 1 0.1`：在区间上按步长列表求值。
+    fn table(&self, text: &str) -> Result<String, CalcError> {
+        let (target, rest) = split_target(text);
+        let func = self.resolve(target)?;
+        let args = call_args(target, &func);
+
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        let [var, start, end, step] = words[..] else {
+            return Err(usage("table <function> <var> <start> <end> <step>"));
+        };
+        let (Ok(start), Ok(end), Ok(step)) = (
+            start.parse::<f64>(),
+            end.parse::<f64>(),
+            step.parse::<f64>(),
+        ) else {
+            return Err(CalcError::InvalidArgument(
+                "range must be numbers".to_string(),
+            ));
+        };
+        if step == 0.0 || !((end - start) / step).is_finite() || (end - start) / step < 0.0 {
+            return Err(CalcError::InvalidArgument(
+                "step must move from start towards end".to_string(),
+            ));
+        }
+
+        // 终点受舍入影响时仍然包含在内
+        let rows = ((end - start) / step + 1e-9).floor() + 1.0;
+        if !rows.is_finite() || rows > MAX_TABLE_ROWS as f64 {
+            return Err(CalcError::InvalidArgument(format!(
+                "table is limited to {} rows",
+                MAX_TABLE_ROWS
+            )));
+        }
+        let count = rows as usize;
+        let xs: Vec<f64> = (0..count).map(|i| start + step * i as f64).collect();
+
+        let mut columns = Vec::with_capacity(args.len());
+        for arg in &args {
+            if arg == var {
+                columns.push(xs.clone());
+            } else if let Ok(v) = arg.parse::<f64>() {
+                columns.push(vec![v; count]);
+            } else {
+                return Err(CalcError::InvalidArgument(
+                    "other arguments must be numbers".to_string(),
+                ));
+            }
+        }
+        let columns: Vec<&[f64]> = columns.iter().map(|c| c.as_slice()).collect();
+
+        let ys = func.caculate_batch(
+            &columns,
+            BatchMode::Parallel { threads: 0 },
+            self.function_table.clone(),
+        )?;
+        let rows: Vec<String> = xs
+            .iter()
+            .zip(ys)
+            .map(|(x, y)| format!("{}\t{}", x, y))
+            .collect();
+        Ok(rows.join("\n"))
+    }
+
+    /// `set style plain|unicode|2d|latex`
+    fn set(&mut self, text: &str) -> Result<String, CalcError> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let style = match words[..] {
+            ["style", "plain"] => Style::Plain,
+            ["style", "unicode"] => Style::Unicode,
+            ["style", "2d"] => Style::TwoD,
+            ["style", "latex"] => Style::Latex,
+            _ => return Err(usage("set style plain|unicode|2d|latex")),
+        };
+        self.style = style;
+        Ok(String::new())
+    }
+
+    fn save(&self, path: &str) -> Result<String, CalcError> {
+        if path.is_empty() {
+            return Err(usage("save <file>"));
+        }
+        let table = self.function_table.borrow();
+        table.save(path)?;
+        Ok(format!("saved {} functions to {}", table.len(), path))
+    }
+
+    fn load(&mut self, path: &str) -> Result<String, CalcError> {
+        if path.is_empty() {
+            return Err(usage("load <file>"));
+        }
+        let count = self.function_table.borrow_mut().load(path)?;
+        Ok(format!("loaded {} functions from {}", count, path))
+    }
+
+    /// 按函数名和参数个数排序列出所有函数。
+    fn list(&self) -> String {
+        let table = self.function_table.borrow();
+        let mut funcs: Vec<&Function> = table.iter().collect();
+        funcs.sort_by_key(|f| f.key());
+
+        let lines: Vec<String> = funcs.iter().map(|f| f.render(self.style)).collect();
+        lines.join("\n")
+    }
+
+    /// 输出 `f` 的所有重载（或 `f(x,y)` 这一个）及依赖它们的函数。
+    fn show(&self, target: &str) -> Result<String, CalcError> {
+        let (name, args) = split_call(target);
+        let table = self.function_table.borrow();
+
+        let mut funcs: Vec<&Function> = table
+            .iter()
+            .filter(|f| f.name() == name && (!target.contains('(') || f.arity() == args.len()))
+            .collect();
+        if funcs.is_empty() {
+            return Err(CalcError::InvalidArgument(format!(
+                "no function named {}",
+                target
+            )));
+        }
+        funcs.sort_by_key(|f| f.key());
+
+        let mut lines = Vec::new();
+        for func in funcs {
+            lines.push(func.render(self.style));
+            let dependents: Vec<String> = table
+                .dependents(func.name(), func.arity())
+                .iter()
+                .map(|(n, c)| format!("{}/{}", n, c))
+                .collect();
+            if !dependents.is_empty() {
+                lines.push(format!("  used by {}", dependents.join(", ")));
+            }
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+fn usage(text: &str) -> CalcError {
+    CalcError::InvalidArgument(format!("usage: {}", text))
+}
+
+/// 把 `f(x, 2) x 0 1` 拆成函数部分 `f(x, 2)` 和其余部分 `x 0 1`。
+fn split_target(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    let space = text.find(char::is_whitespace).unwrap_or(text.len());
+    let end = match text.find('(') {
+        Some(open) if open < space => text[open..]
+            .find(')')
+            .map_or(text.len(), |close| open + close + 1),
+        _ => space,
+    };
+    (&text[..end], text[end..].trim())
+}
+
+/// 把 `f(1,2)` 这样的输入拆成函数名和参数列表。
+fn split_call(text: &str) -> (String, Vec<String>) {
+    let vec: Vec<&str> = text.trim().split(['(', ')']).collect();
+    let args = match vec.get(1) {
+        Some(args) => args
+            .split([',', ' '])
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect(),
+        None => Vec::new(),
+    };
+    (vec[0].trim().to_string(), args)
+}
+
+/// 调用中写出的参数；只写函数名时取函数的形参名。
+fn call_args(target: &str, func: &Function) -> Vec<String> {
+    if target.contains('(') {
+        return split_call(target).1;
+    }
+    match &func.symble {
+        Expr::Func(_, params) => params.iter().map(|p| p.to_string()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// 参数全部是数字时返回这些数字，用于在某一点求值。
+fn numeric_args(args: &[String]) -> Option<Vec<f64>> {
+    args.iter().map(|a| a.parse::<f64>().ok()).collect()
+}
+
+/// 去掉积分变量后其余参数的数值。
+fn fixed_args(args: &[String], is_var: impl Fn(&str) -> bool) -> Result<Vec<f64>, CalcError> {
+    args.iter()
+        .filter(|arg| !is_var(arg))
+        .map(|arg| {
+            arg.parse::<f64>().map_err(|_| {
+                CalcError::InvalidArgument(format!("argument {} must be a number", arg))
+            })
+        })
+        .collect()
+}
+
+/// 积分上下限：数字（包括 `inf`）或表达式，`offset` 为它在整行中的字符偏移。
+fn parse_bound(text: &str, offset: usize) -> Result<Expr, CalcError> {
+    match text.parse::<f64>() {
+        Ok(v) => Ok(Expr::Const(v)),
+        Err(_) => Expr::parse(text).map_err(|e| e.shift(offset)),
+    }
+}
+
+/// 子串切片 `part` 在 `text` 中的字符偏移。
+fn char_offset(text: &str, part: &str) -> usize {
+    let start = part.as_ptr() as usize - text.as_ptr() as usize;
+    text[..start].chars().count()
+}
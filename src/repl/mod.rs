@@ -0,0 +1,63 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{error::CalcError, function::FunctionTable, pretty::Style};
+
+pub mod implement;
+pub mod script;
+
+/// 命令行会话：每行一条命令，例如 `def f(x)=x^2`、`eval f(2)`、`diff f x`。
+///
+/// 交互式界面和脚本都通过 [`Session::execute`] 执行命令。
+pub struct Session {
+    function_table: Rc<RefCell<FunctionTable>>,
+    /// 输出函数时使用的格式
+    style: Style,
+}
+
+/// 一条命令的执行结果。
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// 要输出的文本，可以为空
+    Output(String),
+    /// 结束会话
+    Quit,
+}
+
+/// 脚本中执行失败的一行。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    /// 从 1 开始的行号
+    pub line: usize,
+    /// 从 1 开始的列号，错误没有位置时指向命令开头
+    pub column: usize,
+    /// 出错的那一行
+    pub text: String,
+    pub error: CalcError,
+}
+
+/// `help` 命令输出的说明。
+pub const HELP: &str = "\
+def f(x)=...                 define a function
+redef f(x)=...               define or replace a function
+del f | f(x,y)               delete a function
+eval <expr>                  evaluate, e.g. eval f(2)+1
+check <expr>                 evaluate and report the first NaN or infinity
+diff f x [symbolic]          derivative of f with respect to x
+int f x                      indefinite integral of f with respect to x
+partial f x y ...            higher order partial derivative
+grad f | f(1,2)              gradient, symbolic or at a point
+hess f | f(1,2)              Hessian matrix, symbolic or at a point
+jac f g ... (x,y) | (1,2)    Jacobian matrix of several functions
+quad f(x,2) x 0 inf          definite integral over x
+multiquad f(x,y) x 0 1; y 0 x [; mc <samples> <seed> | ; sobol <samples>]
+                             multiple integral, outermost variable first
+table f(x,2) x 0 1 0.1       tabulate f over a range
+latex f | f(x,y)             show a function as LaTeX
+save <file>                  save all functions in dependency order
+load <file>                  define the functions saved in a file
+list                         list all functions
+show f                       show the definitions of f
+set style plain|unicode|2d|latex
+                             choose how functions are printed
+help                         show this message
+quit                         leave";
@@ -0,0 +1,62 @@
+use crate::repl::{Outcome, ScriptError, Session};
+
+impl Session {
+    /// 逐行执行脚本，每条命令的非空输出交给 `output`，遇到 `quit` 时停止。
+    ///
+    /// `keep_going` 为 false 时在第一个错误处停止，否则执行完所有行；返回所有失败的行。
+    pub fn run_script(
+        &mut self,
+        script: &str,
+        keep_going: bool,
+        mut output: impl FnMut(&str),
+    ) -> Vec<ScriptError> {
+        let mut errors = Vec::new();
+
+        for (i, text) in script.lines().enumerate() {
+            match self.execute(text) {
+                Ok(Outcome::Output(res)) => {
+                    if !res.is_empty() {
+                        output(&res);
+                    }
+                }
+                Ok(Outcome::Quit) => break,
+                Err(error) => {
+                    let column = match error.span() {
+                        Some(span) => span.start + 1,
+                        None => text.chars().take_while(|c| c.is_whitespace()).count() + 1,
+                    };
+                    errors.push(ScriptError {
+                        line: i + 1,
+                        column,
+                        text: text.to_string(),
+                        error,
+                    });
+                    if !keep_going {
+                        break;
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+impl ScriptError {
+    /// 生成形如下面的错误信息，`file` 为脚本的路径：
+    ///
+    /// ```text
+    /// model.calc:3:13: error: unexpected end of input
+    ///   | def g(x)=(x+
+    ///   |             ^
+    /// ```
+    pub fn render(&self, file: &str) -> String {
+        format!(
+            "{}:{}:{}: {}",
+            file,
+            self.line,
+            self.column,
+            self.error.render(&self.text)
+        )
+    }
+}
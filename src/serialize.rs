@@ -0,0 +1,317 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error, ser::SerializeStruct};
+
+use crate::{
+    expr::Expr,
+    function::{DefineMode, Function, FunctionKey, FunctionTable},
+};
+
+const TRIG_NAMES: [&str; 9] = [
+    "sin", "cos", "tan", "arcsin", "arccos", "arctan", "csc", "sec", "cot",
+];
+
+/// 常数；NaN 和 ±∞ 不是合法的 JSON 数字，写成字符串 `"nan"`、`"inf"`、`"-inf"`。
+struct Number(f64);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberRepr {
+    Value(f64),
+    Text(String),
+}
+
+impl Serialize for Number {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            v if v.is_finite() => serializer.serialize_f64(v),
+            v if v.is_nan() => serializer.serialize_str("nan"),
+            v if v > 0.0 => serializer.serialize_str("inf"),
+            _ => serializer.serialize_str("-inf"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Number {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match NumberRepr::deserialize(deserializer)? {
+            NumberRepr::Value(v) => Ok(Number(v)),
+            NumberRepr::Text(text) => match text.as_str() {
+                "nan" => Ok(Number(f64::NAN)),
+                "inf" => Ok(Number(f64::INFINITY)),
+                "-inf" => Ok(Number(f64::NEG_INFINITY)),
+                _ => Err(D::Error::custom(format!("invalid number: {}", text))),
+            },
+        }
+    }
+}
+
+/// 反序列化时 `Expr` 节点的原始形式。
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Node {
+    op: String,
+    name: Option<String>,
+    value: Option<Number>,
+    #[serde(default)]
+    args: Vec<Expr>,
+}
+
+/// `Expr` 序列化为以 `op` 区分种类的树：
+///
+/// ```text
+/// {"op":"const","value":2.5}              常数，NaN 和 ±∞ 写成 "nan"、"inf"、"-inf"
+/// {"op":"var","name":"x"}                 变量
+/// {"op":"call","name":"f","args":[..]}    调用用户定义的函数
+/// {"op":"add","args":[a,b]}               add、sub、mul、div、pow、eq 都有两个参数
+/// {"op":"neg","args":[a]}                 负号
+/// {"op":"log","args":[base,arg]}          以 base 为底的对数
+/// {"op":"sin","args":[a]}                 三角函数，op 为函数名
+/// ```
+impl Serialize for Expr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (op, args): (&str, Vec<&Expr>) = match self {
+            Expr::Const(v) => {
+                let mut node = serializer.serialize_struct("Expr", 2)?;
+                node.serialize_field("op", "const")?;
+                node.serialize_field("value", &Number(*v))?;
+                return node.end();
+            }
+            Expr::Var(name) => {
+                let mut node = serializer.serialize_struct("Expr", 2)?;
+                node.serialize_field("op", "var")?;
+                node.serialize_field("name", name)?;
+                return node.end();
+            }
+            Expr::Func(name, args) => {
+                let mut node = serializer.serialize_struct("Expr", 3)?;
+                node.serialize_field("op", "call")?;
+                node.serialize_field("name", name)?;
+                node.serialize_field("args", args)?;
+                return node.end();
+            }
+            Expr::Add(x, y) => ("add", vec![x, y]),
+            Expr::Sub(x, y) => ("sub", vec![x, y]),
+            Expr::Mul(x, y) => ("mul", vec![x, y]),
+            Expr::Div(x, y) => ("div", vec![x, y]),
+            Expr::Power(x, y) => ("pow", vec![x, y]),
+            Expr::Log(x, y) => ("log", vec![x, y]),
+            Expr::Equal(x, y) => ("eq", vec![x, y]),
+            Expr::Neg(x) => ("neg", vec![x]),
+            Expr::Trifuncs(name, x) => (name.as_str(), vec![x]),
+        };
+
+        let mut node = serializer.serialize_struct("Expr", 2)?;
+        node.serialize_field("op", op)?;
+        node.serialize_field("args", &args)?;
+        node.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Expr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let Node {
+            op,
+            name,
+            value,
+            args,
+        } = Node::deserialize(deserializer)?;
+        let name = || name.ok_or_else(|| D::Error::custom(format!("{} needs a name", op)));
+
+        // 除 call 以外每种节点的参数个数是固定的
+        let arity = match op.as_str() {
+            "const" => {
+                let Some(Number(v)) = value else {
+                    return Err(D::Error::custom("const needs a value"));
+                };
+                return Ok(Expr::Const(v));
+            }
+            "var" => return Ok(Expr::Var(name()?)),
+            "call" => return Ok(Expr::Func(name()?, args)),
+            "neg" => 1,
+            "add" | "sub" | "mul" | "div" | "pow" | "log" | "eq" => 2,
+            trig if TRIG_NAMES.contains(&trig) => 1,
+            _ => return Err(D::Error::custom(format!("unknown op: {}", op))),
+        };
+        if args.len() != arity {
+            return Err(D::Error::custom(format!(
+                "{} expects {} arguments, found {}",
+                op,
+                arity,
+                args.len()
+            )));
+        }
+
+        let mut args = args.into_iter().map(Box::new);
+        let mut arg = || args.next().expect("argument count is checked above");
+        Ok(match op.as_str() {
+            "add" => Expr::Add(arg(), arg()),
+            "sub" => Expr::Sub(arg(), arg()),
+            "mul" => Expr::Mul(arg(), arg()),
+            "div" => Expr::Div(arg(), arg()),
+            "pow" => Expr::Power(arg(), arg()),
+            "log" => Expr::Log(arg(), arg()),
+            "eq" => Expr::Equal(arg(), arg()),
+            "neg" => Expr::Neg(arg()),
+            _ => Expr::Trifuncs(op.clone(), arg()),
+        })
+    }
+}
+
+/// `Function` 的序列化形式：`{"name":"f","params":["x","y"],"body":<Expr>}`。
+#[derive(Serialize)]
+struct FunctionRef<'a> {
+    name: &'a str,
+    params: Vec<&'a str>,
+    body: &'a Expr,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FunctionRepr {
+    name: String,
+    params: Vec<String>,
+    body: Expr,
+}
+
+impl Serialize for Function {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Expr::Func(name, params) = &self.symble else {
+            return Err(serde::ser::Error::custom("illegal function"));
+        };
+        let params = params
+            .iter()
+            .map(|p| match p {
+                Expr::Var(name) => Ok(name.as_str()),
+                _ => Err(serde::ser::Error::custom("illegal function")),
+            })
+            .collect::<Result<_, _>>()?;
+
+        FunctionRef {
+            name,
+            params,
+            body: &self.body,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Function {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = FunctionRepr::deserialize(deserializer)?;
+        Ok(Function {
+            symble: Expr::Func(repr.name, repr.params.into_iter().map(Expr::Var).collect()),
+            body: repr.body,
+        })
+    }
+}
+
+/// 函数表中的键：`{"name":"f","arity":1}`。
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Key {
+    name: String,
+    arity: usize,
+}
+
+/// 不来自函数调用的依赖，例如导函数 `f'` 由 `f` 求导得到。
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Derived {
+    name: String,
+    arity: usize,
+    from: Key,
+}
+
+/// `FunctionTable` 的序列化形式：
+///
+/// ```text
+/// {
+///   "functions": [<Function>, ..],
+///   "derived": [{"name":"f'","arity":1,"from":{"name":"f","arity":1}}, ..]
+/// }
+/// ```
+///
+/// `functions` 按依赖顺序排列；缓存的偏导数不保存。
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TableRepr<F> {
+    functions: Vec<F>,
+    #[serde(default)]
+    derived: Vec<Derived>,
+}
+
+impl Serialize for FunctionTable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let functions = self.dependency_order();
+
+        let mut derived = Vec::new();
+        for func in &functions {
+            let (name, arity) = func.key();
+            let calls: HashSet<FunctionKey> = func.body.called_functions().into_iter().collect();
+
+            for from in self.dependencies(&name, arity) {
+                if !calls.contains(&from) {
+                    derived.push(Derived {
+                        name: name.clone(),
+                        arity,
+                        from: Key {
+                            name: from.0,
+                            arity: from.1,
+                        },
+                    });
+                }
+            }
+        }
+
+        TableRepr { functions, derived }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FunctionTable {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = TableRepr::<Function>::deserialize(deserializer)?;
+
+        // 按普通定义的规则逐个定义，重复或循环调用的函数会被拒绝
+        let mut table = FunctionTable::new();
+        for func in repr.functions {
+            table
+                .define(func, DefineMode::Strict)
+                .map_err(D::Error::custom)?;
+        }
+
+        // 调用的函数都必须在表中
+        for func in table.iter() {
+            for (name, arity) in func.body.called_functions() {
+                if !table.contains(&name, arity) {
+                    return Err(D::Error::custom(format!(
+                        "{} calls unknown function {}/{}",
+                        func.name(),
+                        name,
+                        arity
+                    )));
+                }
+            }
+        }
+
+        for Derived { name, arity, from } in repr.derived {
+            let key = (name, arity);
+            let from = (from.name, from.arity);
+            if !table.contains(&key.0, key.1) || !table.contains(&from.0, from.1) {
+                return Err(D::Error::custom(format!(
+                    "unknown dependency {}/{} -> {}/{}",
+                    key.0, key.1, from.0, from.1
+                )));
+            }
+            if key == from || table.dependents(&key.0, key.1).contains(&from) {
+                return Err(D::Error::custom(format!(
+                    "circular dependency between {}/{} and {}/{}",
+                    key.0, key.1, from.0, from.1
+                )));
+            }
+            table.add_dependency(key, from);
+        }
+
+        Ok(table)
+    }
+}
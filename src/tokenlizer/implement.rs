@@ -1,12 +1,22 @@
 use std::f64::consts::PI;
 
-use crate::{expr::Token, tokenlizer::Tokenlizer};
+use crate::{
+    error::CalcError,
+    expr::{Span, SpannedToken, Token},
+    tokenlizer::Tokenlizer,
+};
 
 impl Tokenlizer {
+    /// 标识符以字母开头，其后可以是字母、数字和 '_'，末尾可以带若干 '\''，
+    /// 例如 `x1`、`f_xy`、`f''`。
     fn tokenlize_alpha(&mut self, text: &[char]) -> Token {
         let mut res = String::new();
 
-        while self.i < text.len() && text[self.i].is_alphabetic() {
+        while self.i < text.len() && (text[self.i].is_alphanumeric() || text[self.i] == '_') {
+            res.push(text[self.i]);
+            self.i += 1;
+        }
+        while self.i < text.len() && text[self.i] == '\'' {
             res.push(text[self.i]);
             self.i += 1;
         }
@@ -22,65 +32,146 @@ impl Tokenlizer {
         Token::Identifier(res)
     }
 
-    fn tokenlize_number(&mut self, text: &[char]) -> Result<Token, anyhow::Error> {
-        let mut res = 0.0;
+    /// 跳过连续的、满足 `pred` 的字符，返回跳过的内容。
+    fn take_while(&mut self, text: &[char], pred: impl Fn(char) -> bool) -> String {
+        let mut res = String::new();
+
+        while self.i < text.len() && pred(text[self.i]) {
+            res.push(text[self.i]);
+            self.i += 1;
+        }
+
+        res
+    }
+
+    /// 解析 `0x..` / `0b..` 形式的整数字面量，调用时 `self.i` 指向前缀的 `0`。
+    fn tokenlize_radix(&mut self, text: &[char], radix: u32) -> Result<Token, CalcError> {
+        let start = self.i;
+        self.i += 2;
+
+        let digits = self.take_while(text, |c| c.is_ascii_alphanumeric());
+        let literal: String = text[start..self.i].iter().collect();
+
+        match u64::from_str_radix(&digits, radix) {
+            Ok(v) => Ok(Token::Const(v as f64)),
+            Err(_) => Err(CalcError::Lex {
+                message: format!("malformed number literal `{}`", literal),
+                span: Span::new(start, self.i),
+            }),
+        }
+    }
+
+    /// 解析十进制数字字面量：整数、小数、`.5` 这类省略整数部分的形式以及 `2.5e-3` 这类科学计数法。
+    fn tokenlize_number(&mut self, text: &[char]) -> Result<Token, CalcError> {
+        let start = self.i;
+
+        if text[self.i] == '0' && self.i + 1 < text.len() {
+            match text[self.i + 1] {
+                'x' | 'X' => return self.tokenlize_radix(text, 16),
+                'b' | 'B' => return self.tokenlize_radix(text, 2),
+                _ => {}
+            }
+        }
+
+        // 报错时把紧随其后的数字、字母和小数点一并显示出来，例如 `1.2.3`
+        let malformed = |pos: usize| {
+            let mut end = pos;
+            while end < text.len() && (text[end].is_ascii_alphanumeric() || text[end] == '.') {
+                end += 1;
+            }
+            let literal: String = text[start..end].iter().collect();
+            CalcError::Lex {
+                message: format!("malformed number literal `{}`", literal),
+                span: Span::new(pos, pos + 1),
+            }
+        };
+
+        let int_part = self.take_while(text, |c| c.is_ascii_digit());
+        let mut frac_part = String::new();
+        if self.i < text.len() && text[self.i] == '.' {
+            self.i += 1;
+            frac_part = self.take_while(text, |c| c.is_ascii_digit());
+        }
 
-        while self.i < text.len() && text[self.i].is_ascii_digit() {
-            res += text[self.i].to_string().parse::<f64>()?;
+        if int_part.is_empty() && frac_part.is_empty() {
+            return Err(malformed(self.i));
+        }
+
+        if self.i < text.len() && (text[self.i] == 'e' || text[self.i] == 'E') {
             self.i += 1;
+            if self.i < text.len() && (text[self.i] == '+' || text[self.i] == '-') {
+                self.i += 1;
+            }
+            if self.take_while(text, |c| c.is_ascii_digit()).is_empty() {
+                return Err(malformed(self.i));
+            }
         }
 
-        Ok(Token::Const(res))
+        // 诸如 `1.2.3`、`1e5.0` 的残留部分不能被当作下一个 token
+        if self.i < text.len() && (text[self.i] == '.' || text[self.i].is_ascii_digit()) {
+            return Err(malformed(self.i));
+        }
+
+        let literal: String = text[start..self.i].iter().collect();
+        match literal.parse::<f64>() {
+            Ok(v) => Ok(Token::Const(v)),
+            Err(_) => Err(malformed(start)),
+        }
     }
 
     fn tokenlize_operator(&self, c: char) -> Token {
-        return Token::Operator(c);
+        Token::Operator(c)
     }
 
     fn is_operator(c: char) -> bool {
-        return c == '+'
-            || c == '-'
-            || c == '/'
-            || c == '*'
-            || c == '^'
-            || c == '('
-            || c == ')'
-            || c == '['
-            || c == ']'
-            || c == '{'
-            || c == '}'
-            || c == '=';
+        matches!(
+            c,
+            '+' | '-' | '/' | '*' | '^' | '(' | ')' | '[' | ']' | '{' | '}' | '=' | ','
+        )
+    }
+
+    fn is_number_start(text: &[char], i: usize) -> bool {
+        text[i].is_ascii_digit()
+            || (text[i] == '.' && i + 1 < text.len() && text[i + 1].is_ascii_digit())
     }
 
-    pub fn tokenlize(&mut self) -> Result<Vec<Token>, anyhow::Error> {
-        let mut tokens = Vec::<Token>::new();
+    pub fn tokenlize(&mut self) -> Result<Vec<SpannedToken>, CalcError> {
+        let mut tokens = Vec::<SpannedToken>::new();
         let text: Vec<char> = self.orig_text.chars().collect();
 
         while self.i < text.len() {
+            let start = self.i;
+            let token;
+
             if text[self.i].is_alphabetic() {
-                tokens.push(self.tokenlize_alpha(&text));
-                continue;
-            } else if text[self.i].is_ascii_digit() {
-                tokens.push(self.tokenlize_number(&text)?);
-                continue;
+                token = self.tokenlize_alpha(&text);
+            } else if Self::is_number_start(&text, self.i) {
+                token = self.tokenlize_number(&text)?;
             } else if Self::is_operator(text[self.i]) {
-                tokens.push(self.tokenlize_operator(text[self.i]));
-            } else if text[self.i].is_whitespace() || text[self.i] == ',' {
+                token = self.tokenlize_operator(text[self.i]);
+                self.i += 1;
+            } else if text[self.i].is_whitespace() {
                 self.i += 1;
                 continue;
             } else {
-                return Err(anyhow::Error::msg("unknown token type"));
+                return Err(CalcError::Lex {
+                    message: format!("unknown token type `{}`", text[self.i]),
+                    span: Span::new(start, start + 1),
+                });
             }
 
-            self.i += 1;
+            tokens.push(SpannedToken {
+                token,
+                span: Span::new(start, self.i),
+            });
         }
 
         Ok(tokens)
     }
 
-    pub fn new(text: &String) -> Tokenlizer {
+    pub fn new(text: &str) -> Tokenlizer {
         Tokenlizer {
-            orig_text: text.clone(),
+            orig_text: text.to_string(),
             i:0,
         }
     }